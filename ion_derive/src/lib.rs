@@ -0,0 +1,91 @@
+//! `#[derive(Observable)]`, which generates the accessors and change tracking for app state:
+//!
+//! ```ignore
+//! #[derive(Observable)]
+//! struct AppState {
+//!     items: Vec<TodoItem>,
+//!     changes: Changes<AppState>,
+//! }
+//! ```
+//!
+//! Every field except the `Changes` one gets `get_x`, `get_x_mut`, `set_x` and `subscribe_x`, with
+//! the same visibility as the field.
+
+extern crate proc_macro;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+
+#[proc_macro_derive(Observable)]
+pub fn derive_observable(input: TokenStream) -> TokenStream {
+    // Structs declared by `observable!` name their `Changes` field through `$crate`, which syn cannot
+    // parse. Only the last segment of that type is looked at, so any path will do.
+    let input = input.to_string().replace("$crate", "ion");
+    let ast = syn::parse_derive_input(&input).unwrap();
+    impl_observable(&ast).parse().unwrap()
+}
+
+fn impl_observable(ast: &syn::DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let fields = match ast.body {
+        syn::Body::Struct(syn::VariantData::Struct(ref fields)) => fields,
+        _ => panic!("#[derive(Observable)] only works on structs with named fields"),
+    };
+    let changes = match fields.iter().find(|field| is_changes(&field.ty)) {
+        Some(field) => field.ident.as_ref().unwrap(),
+        None => panic!("#[derive(Observable)] needs a field of type `Changes<{}>`", name),
+    };
+
+    let accessors = fields.iter().filter(|field| !is_changes(&field.ty)).map(|field| {
+        let field_name = field.ident.as_ref().unwrap();
+        let field_str = field_name.as_ref();
+        let vis = &field.vis;
+        let ty = &field.ty;
+        let get = syn::Ident::new(format!("get_{}", field_name));
+        let get_mut = syn::Ident::new(format!("get_{}_mut", field_name));
+        let set = syn::Ident::new(format!("set_{}", field_name));
+        let subscribe = syn::Ident::new(format!("subscribe_{}", field_name));
+
+        quote! {
+            #[allow(dead_code)]
+            #vis fn #get(&self) -> &#ty { &self.#field_name }
+            #[allow(dead_code)]
+            #vis fn #get_mut(&mut self) -> ::ion::observable::FieldMut<Self, #ty> {
+                ::ion::observable::FieldMut::new(self, #field_str, |s| &s.#field_name, |s| &mut s.#field_name)
+            }
+            #[allow(dead_code)]
+            #vis fn #set(&mut self, val: #ty) {
+                self.#field_name = val;
+                ::ion::observable::Changes::changed(self, #field_str);
+            }
+            /// Calls `callback` with the new state every time the field changes
+            #[allow(dead_code)]
+            #vis fn #subscribe<F: Fn(&Self) + 'static>(&mut self, callback: F) {
+                self.#changes.subscribe(#field_str, callback);
+            }
+        }
+    });
+
+    quote! {
+        impl #impl_generics ::ion::observable::Observable for #name #ty_generics #where_clause {
+            fn changes(&self) -> &::ion::observable::Changes<Self> { &self.#changes }
+            fn changes_mut(&mut self) -> &mut ::ion::observable::Changes<Self> { &mut self.#changes }
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #(#accessors)*
+        }
+    }
+}
+
+/// Whether the field is the one that keeps track of changes
+fn is_changes(ty: &syn::Ty) -> bool {
+    match *ty {
+        syn::Ty::Path(None, ref path) => path.segments.last().map_or(false, |segment| segment.ident == "Changes"),
+        _ => false,
+    }
+}
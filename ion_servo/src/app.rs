@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use ion::component::Component;
+use ion::component::Scope;
+use ion::component::component_keyed;
+use ion::history::History;
+use ion::html::HtmlElement;
+use ion::html::HtmlNode;
+use ion::html::fragment;
+use ion::memo::memo;
+use ion::observable::Observable;
+use ion::persist::Persist;
+use ion::tags::*;
+
+make_app_setup!{ pub fn app_setup() app_thread_state = APP_STATE, render = render, update = update,
+                 devtools = cfg!(debug_assertions), persist = Persist::new("ion-todo", 1) }
+thread_local!(static APP_STATE: RefCell<AppState> = RefCell::new(AppState::new()));
+thread_local!(static HISTORY: RefCell<History<AppState>> = RefCell::new(History::new(100)));
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct TodoItem {
+    id: u32,
+    name: String,
+}
+
+observable! {#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)] struct AppState {
+    items : Vec<TodoItem> = vec![TodoItem {id: 1, name: "Testing!".to_string()}],
+    new_item_name: String = "Item Name".to_string(),
+    computed next_id(items): u32 = items.iter().map(|item| item.id).max().unwrap_or(0) + 1,
+}}
+
+#[derive(Debug)]
+enum Msg {
+    Undo,
+    Redo,
+    Add,
+    SetNewItemName(String),
+    Rename(u32, String),
+}
+
+fn update(state: &mut AppState, msg: Msg) {
+    HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        match msg {
+            Msg::Undo => { history.undo(state); },
+            Msg::Redo => { history.redo(state); },
+            Msg::Add => history.record(state, |state| state.batch(|state| {
+                let name = state.get_new_item_name().clone();
+                let id = *state.next_id();
+                state.get_items_mut().push(TodoItem { id, name });
+                state.set_new_item_name("Item Name".to_string());
+            })),
+            // Keys like the arrows send these too, but History leaves out changes that change nothing
+            Msg::SetNewItemName(name) => history.record_coalesced(state, "new item name", |state| state.set_new_item_name(name)),
+            Msg::Rename(id, name) => {
+                // Every keystroke sends a message, but the whole edit is undone at once
+                history.record_coalesced(state, format!("rename {}", id), |state| {
+                    for item in state.get_items_mut().iter_mut() {
+                        if item.id == id {
+                            item.name = name.clone();
+                        }
+                    }
+                });
+            },
+        }
+    })
+}
+
+/// Whether an item is being edited only matters to the item, so it is kept out of AppState
+struct ItemView {
+    editing: bool,
+}
+
+impl Component for ItemView {
+    type Props = TodoItem;
+
+    fn create(_: &TodoItem) -> ItemView {
+        ItemView { editing: false }
+    }
+
+    fn render(&self, item: &TodoItem, scope: &Scope<ItemView>) -> HtmlNode {
+        let scope = scope.clone();
+
+        div()
+            .child(if self.editing {
+                render_edit(item)
+            } else {
+                p().text(&item.name)
+            })
+            .child(button().text(if self.editing { "Save" } else { "Edit" }).on("click", move |_| {
+                scope.update(|view| view.editing = !view.editing);
+            }))
+            .into()
+    }
+}
+
+fn render_item(item: &TodoItem) -> HtmlNode {
+    component_keyed::<ItemView, _>(item.id, item.clone())
+}
+
+fn render_edit(item: &TodoItem) -> HtmlElement {
+    let input = textarea().key(format!("item_{}", item.id)).value(item.name.as_str());
+    let item_id = item.id.clone();
+
+    input.on_events_message(vec!["input", "keyup"], move |event| Msg::Rename(item_id, event.value.clone()))
+}
+
+fn render_add(state: &AppState) -> HtmlElement {
+    let input = textarea().key("add_input").value(state.get_new_item_name().as_str());
+    let input = input.on_events_message(vec!["input", "keyup"], |event| Msg::SetNewItemName(event.value.clone()));
+
+    div()
+        .child(h3().text("Add item"))
+        .child(input)
+        .child(button().text("+").on_message("click", |_| Msg::Add))
+}
+
+fn render_history() -> HtmlElement {
+    let (can_undo, can_redo) = HISTORY.with(|history| {
+        let history = history.borrow();
+        (history.can_undo(), history.can_redo())
+    });
+    div()
+        .child(button().text("Undo").disabled(!can_undo).on_message("click", |_| Msg::Undo))
+        .child(button().text("Redo").disabled(!can_redo).on_message("click", |_| Msg::Redo))
+}
+
+fn render(state: &AppState) -> HtmlNode {
+    fragment(vec![
+        html!{ <h1>"Todo List"</h1> }.into(),
+        render_history().into(),
+        memo("items", state.changes().revision("items"), || html!{ <div>{for state.get_items().iter().map(render_item)}</div> }),
+        render_add(state).into(),
+    ])
+}
@@ -0,0 +1,261 @@
+//! Renders ion apps into the document of the forked Servo, which calls `app_main` once the page
+//! exists. This is the only part of ion that reaches into Servo's script internals, and lives in a
+//! crate of its own so the rest of ion builds without the fork.
+//!
+//! Nothing polls for changes while the app is idle: the first frame is rendered once the page loaded,
+//! and after that a frame is only rendered when something asks for one. The fork provides
+//! `script_thread::IonFrameWaker` for that, a handle that can be cloned and sent to any thread, whose
+//! `wake` queues a task on the script thread of the document it was made for. That task calls
+//! `ION_APPLICATION_FRAME_CALLBACK` with the document. Events ask for a frame with it, and so do the
+//! threads that finish background tasks, wake futures or wait for timers, through `task::set_waker`.
+
+extern crate ion;
+extern crate servo;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use servo::script::dom::document::Document;
+use servo::script::dom::bindings::str::DOMString;
+use servo::script::dom::eventtarget::EventTarget;
+use servo::script::dom::eventtarget::RustEventHandler;
+use servo::script::dom::bindings::codegen::Bindings::DocumentBinding::DocumentMethods;
+use servo::script::dom::bindings::codegen::Bindings::DocumentBinding::ElementCreationOptions;
+use servo::script::dom::bindings::codegen::Bindings::ElementBinding::ElementMethods;
+use servo::script::dom::bindings::codegen::Bindings::NodeBinding::NodeMethods;
+use servo::script::dom::bindings::inheritance::Castable;
+use servo::script::dom::bindings::root::DomRoot;
+use servo::script::dom::element::Element;
+use servo::script::dom::node::Node;
+use servo::script::script_thread::ION_APPLICATION_FRAME_CALLBACK;
+use servo::script::script_thread::IonFrameWaker;
+
+use ion::backend;
+use ion::backend::Backend;
+use ion::html;
+use ion::html::Event;
+use ion::html::Listener;
+use ion::html::Property;
+use ion::task;
+
+/// Whether a frame was asked for and not rendered yet, so a burst of wakes only queues one
+static FRAME_QUEUED: AtomicBool = AtomicBool::new(false);
+
+/// Set once the page loaded, for events to ask for frames with
+thread_local!(static WAKER: RefCell<Option<IonFrameWaker>> = RefCell::new(None));
+
+fn ds<T>(str: T) -> DOMString where T: ToString { DOMString::from_string(str.to_string()) }
+
+/// Servo only lends out its document while it runs one of our callbacks, so a backend is made for each one
+pub struct ServoBackend<'a> {
+    doc: &'a Document,
+}
+
+impl<'a> ServoBackend<'a> {
+    pub fn new(doc: &'a Document) -> ServoBackend<'a> {
+        ServoBackend { doc }
+    }
+
+    fn element<'b>(node: &'b DomRoot<Node>) -> &'b Element {
+        node.downcast::<Element>().expect("Expected an element, found another kind of node")
+    }
+
+    /// Wraps a listener for Servo, which calls it with its own DOM types
+    fn handler(id: &str, kind: &str, listener: &Listener) -> RustEventHandler {
+        let id = id.to_string();
+        let kind = kind.to_string();
+        let listener = listener.clone();
+        RustEventHandler {
+            handler: Rc::new(move |doc, _| {
+                let mut backend = ServoBackend::new(doc);
+                let value = match doc.GetElementById(ds(&id)) {
+                    Some(elem) => backend.value(&DomRoot::upcast(elem)),
+                    None => String::new(),
+                };
+                backend::dispatch(&mut backend, &listener, &Event { kind: kind.clone(), target: id.clone(), value });
+            })
+        }
+    }
+}
+
+impl<'a> Backend for ServoBackend<'a> {
+    type Node = DomRoot<Node>;
+
+    fn root(&self) -> DomRoot<Node> {
+        let body_collection = self.doc.GetElementsByTagName(ds("body"));
+        DomRoot::upcast(body_collection.elements_iter().last().unwrap())
+    }
+
+    fn element_by_id(&self, id: &str) -> DomRoot<Node> {
+        DomRoot::upcast(self.doc.GetElementById(ds(id)).expect("Patch refers to an element that is not in the document"))
+    }
+
+    fn children(&self, parent: &DomRoot<Node>) -> Vec<DomRoot<Node>> {
+        let mut children = vec![];
+        let mut cursor = parent.GetFirstChild();
+        while let Some(node) = cursor {
+            cursor = node.GetNextSibling();
+            children.push(node);
+        }
+        children
+    }
+
+    fn create_element(&mut self, tag: &str) -> DomRoot<Node> {
+        let elem = self.doc.CreateElement(ds(tag), unsafe { &ElementCreationOptions::empty(self.doc.window().get_cx()) });
+        DomRoot::upcast(elem.unwrap())
+    }
+
+    fn create_text(&mut self, text: &str) -> DomRoot<Node> {
+        DomRoot::upcast(self.doc.CreateTextNode(ds(text)))
+    }
+
+    fn set_text(&mut self, node: &DomRoot<Node>, text: &str) {
+        node.SetTextContent(Some(ds(text)));
+    }
+
+    fn insert_before(&mut self, parent: &DomRoot<Node>, node: &DomRoot<Node>, before: Option<&DomRoot<Node>>) {
+        parent.InsertBefore(node, before.map(|n| &**n)).unwrap();
+    }
+
+    fn remove(&mut self, parent: &DomRoot<Node>, node: &DomRoot<Node>) {
+        parent.RemoveChild(node).unwrap();
+    }
+
+    fn set_attribute(&mut self, elem: &DomRoot<Node>, name: &str, value: &str) {
+        Self::element(elem).SetAttribute(ds(name), ds(value)).unwrap();
+    }
+
+    fn remove_attribute(&mut self, elem: &DomRoot<Node>, name: &str) {
+        Self::element(elem).RemoveAttribute(ds(name));
+    }
+
+    fn set_property(&mut self, elem: &DomRoot<Node>, name: &str, value: &Property) {
+        use servo::script::dom::htmlinputelement::HTMLInputElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLInputElementBinding::HTMLInputElementMethods;
+        use servo::script::dom::htmltextareaelement::HTMLTextAreaElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLTextAreaElementBinding::HTMLTextAreaElementMethods;
+        use servo::script::dom::htmlselectelement::HTMLSelectElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLSelectElementBinding::HTMLSelectElementMethods;
+        use servo::script::dom::htmloptionelement::HTMLOptionElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLOptionElementBinding::HTMLOptionElementMethods;
+        use servo::script::dom::htmlbuttonelement::HTMLButtonElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLButtonElementBinding::HTMLButtonElementMethods;
+
+        let elem = Self::element(elem);
+        match (name, value) {
+            ("value", &Property::Text(ref v)) => {
+                // Setting the value moves the cursor, so leave it alone if the user already typed it
+                if let Some(input) = elem.downcast::<HTMLInputElement>() {
+                    if input.Value().to_string() != *v { let _ = input.SetValue(ds(v)); }
+                    return;
+                }
+                if let Some(textarea) = elem.downcast::<HTMLTextAreaElement>() {
+                    if textarea.Value().to_string() != *v { textarea.SetValue(ds(v)); }
+                    return;
+                }
+                if let Some(select) = elem.downcast::<HTMLSelectElement>() {
+                    select.SetValue(ds(v));
+                    return;
+                }
+            },
+            ("checked", &Property::Bool(v)) => {
+                if let Some(input) = elem.downcast::<HTMLInputElement>() {
+                    input.SetChecked(v);
+                    return;
+                }
+            },
+            ("selected", &Property::Bool(v)) => {
+                if let Some(option) = elem.downcast::<HTMLOptionElement>() {
+                    option.SetSelected(v);
+                    return;
+                }
+            },
+            ("disabled", &Property::Bool(v)) => {
+                if let Some(input) = elem.downcast::<HTMLInputElement>() { input.SetDisabled(v); return; }
+                if let Some(textarea) = elem.downcast::<HTMLTextAreaElement>() { textarea.SetDisabled(v); return; }
+                if let Some(select) = elem.downcast::<HTMLSelectElement>() { select.SetDisabled(v); return; }
+                if let Some(button) = elem.downcast::<HTMLButtonElement>() { button.SetDisabled(v); return; }
+            },
+            _ => {}
+        }
+
+        // Anything Servo doesn't expose to rust is reflected as an attribute instead
+        match *value {
+            Property::Text(ref v) => elem.SetAttribute(ds(name), ds(v)).unwrap(),
+            Property::Bool(true) => elem.SetAttribute(ds(name), ds("")).unwrap(),
+            Property::Bool(false) => elem.RemoveAttribute(ds(name)),
+        }
+    }
+
+    fn set_listeners(&mut self, elem: &DomRoot<Node>, id: &str, listeners: &HashMap<String, Listener>) {
+        let target: &EventTarget = elem.upcast::<EventTarget>();
+        target.remove_all_listeners();
+        for (event, listener) in listeners {
+            target.add_event_handler_rust(ds(event), Self::handler(id, event, listener));
+        }
+    }
+
+    fn value(&self, elem: &DomRoot<Node>) -> String {
+        use servo::script::dom::htmlinputelement::HTMLInputElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLInputElementBinding::HTMLInputElementMethods;
+        use servo::script::dom::htmltextareaelement::HTMLTextAreaElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLTextAreaElementBinding::HTMLTextAreaElementMethods;
+        use servo::script::dom::htmlselectelement::HTMLSelectElement;
+        use servo::script::dom::bindings::codegen::Bindings::HTMLSelectElementBinding::HTMLSelectElementMethods;
+
+        if let Some(input) = elem.downcast::<HTMLInputElement>() {
+            return input.Value().to_string();
+        }
+        if let Some(textarea) = elem.downcast::<HTMLTextAreaElement>() {
+            return textarea.Value().to_string();
+        }
+        if let Some(select) = elem.downcast::<HTMLSelectElement>() {
+            return select.Value().to_string();
+        }
+        String::new()
+    }
+
+    fn request_frame(&mut self) {
+        // Queued behind the events Servo already has, so events that arrive together, like input and
+        // keyup, are usually rendered once
+        WAKER.with(|waker| match *waker.borrow() {
+            Some(ref waker) => wake(waker),
+            None => {},
+        });
+    }
+}
+
+/// Asks for `frame_callback` to be called on the script thread, from any thread, unless it already was
+fn wake(waker: &IonFrameWaker) {
+    if !FRAME_QUEUED.swap(true, Ordering::SeqCst) {
+        waker.wake();
+    }
+}
+
+fn frame_callback(doc: &Document) {
+    // Cleared first, so whatever wakes while the frame renders asks for another one
+    FRAME_QUEUED.store(false, Ordering::SeqCst);
+    html::render_frame(&mut ServoBackend::new(doc));
+}
+
+/// Passed to `servo::Servo::new` along with the app's setup function, to start rendering once the page has loaded
+pub fn app_main(doc: &Document) {
+    let window = doc.window();
+    window.deref().upcast::<EventTarget>().add_event_handler_rust(ds("load"), RustEventHandler {
+        handler: Rc::new(|doc, _| {
+            ION_APPLICATION_FRAME_CALLBACK.with(|root| {
+                assert!(root.get().is_none());
+                root.set(Some(frame_callback))
+            });
+            let waker = IonFrameWaker::new(doc);
+            WAKER.with(|own| *own.borrow_mut() = Some(waker.clone()));
+            // Finished tasks, woken futures and due timers ask for a frame from their own threads
+            task::set_waker(move || wake(&waker));
+            frame_callback(doc);
+        })
+    });
+}
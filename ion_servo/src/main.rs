@@ -1,13 +1,12 @@
-#![feature(plugin)]
-#![plugin(interpolate_idents)]
-
 #[macro_use]
-mod observable;
+extern crate ion;
 #[macro_use]
-mod html;
-mod app;
+extern crate ion_derive;
+extern crate ion_servo;
+#[macro_use]
+extern crate serde_derive;
 
-#[macro_use] extern crate maplit;
+mod app;
 
 /*
     This is taken mostly from https://github.com/paulrouget/servo-embedding-example
@@ -96,7 +95,7 @@ fn main() {
                              gl,
                          });
 
-    let mut servo = servo::Servo::new(window.clone(), Some((app::app_setup, html::app_main)));
+    let mut servo = servo::Servo::new(window.clone(), Some((app::app_setup, ion_servo::app_main)));
 
     let url = ServoUrl::parse(&format!("file://{}",  env::current_dir().unwrap()
         .join("app_resources/index.html").to_str().unwrap())).unwrap();
@@ -252,6 +251,9 @@ fn main() {
         }
         glutin::ControlFlow::Continue
     });
+
+    // The window is gone, but the last changes to the app state may not be saved yet
+    ion::persist::finish();
 }
 
 impl WindowMethods for Window {
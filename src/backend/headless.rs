@@ -0,0 +1,266 @@
+//! Runs an app against an in-memory DOM instead of Servo, so it can be tested without a window:
+//!
+//! ```ignore
+//! let mut app = Harness::new(app::app_setup);
+//! let input = app.find_by_key("add_input").unwrap();
+//! input.set_value("Milk");
+//! app.fire(&input, "input");
+//! app.fire(&app.find_by_text("+").unwrap(), "click");
+//! app.frame();
+//! assert!(app.find_by_text("Milk").is_some());
+//! ```
+//!
+//! The render loop keeps its state in thread locals, so each thread can only run one app, once.
+//! That is what the test runner gives every test anyway.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use backend;
+use backend::Backend;
+use html;
+use html::Event;
+use html::Listener;
+use html::Property;
+#[cfg(feature = "persist")]
+use persist;
+use task;
+use timer;
+
+struct NodeData {
+    /// Like the DOM's `nodeName`, "#text" for text nodes
+    tag: String,
+    text: String,
+    attributes: HashMap<String, String>,
+    properties: HashMap<String, Property>,
+    listeners: HashMap<String, Listener>,
+    children: Vec<Node>,
+}
+
+/// A node of the in-memory DOM. Clones refer to the same node.
+#[derive(Clone)]
+pub struct Node(Rc<RefCell<NodeData>>);
+
+impl Node {
+    fn new(tag: &str, text: &str) -> Node {
+        Node(Rc::new(RefCell::new(NodeData {
+            tag: tag.to_string(),
+            text: text.to_string(),
+            attributes: HashMap::new(),
+            properties: HashMap::new(),
+            listeners: HashMap::new(),
+            children: vec![],
+        })))
+    }
+
+    pub fn is_text(&self) -> bool { self.0.borrow().tag == "#text" }
+    pub fn tag(&self) -> String { self.0.borrow().tag.clone() }
+    pub fn id(&self) -> String { self.attribute("id").unwrap_or_default() }
+    pub fn class(&self) -> String { self.attribute("class").unwrap_or_default() }
+    pub fn style(&self) -> String { self.attribute("style").unwrap_or_default() }
+    pub fn attribute(&self, name: &str) -> Option<String> { self.0.borrow().attributes.get(name).cloned() }
+    pub fn property(&self, name: &str) -> Option<Property> { self.0.borrow().properties.get(name).cloned() }
+    pub fn children(&self) -> Vec<Node> { self.0.borrow().children.clone() }
+
+    /// The text of the node and all its descendants, like the DOM's `textContent`
+    pub fn text(&self) -> String {
+        if self.is_text() {
+            return self.0.borrow().text.clone();
+        }
+        self.0.borrow().children.iter().map(Node::text).collect()
+    }
+
+    /// The `value` property, which is what a user typing into a textarea changes
+    pub fn value(&self) -> String {
+        match self.property("value") {
+            Some(Property::Text(value)) => value,
+            _ => String::new(),
+        }
+    }
+
+    /// Changes the value of a form control, the way typing into it would. Like in a real DOM, this
+    /// does not fire any events.
+    pub fn set_value(&self, value: &str) {
+        self.0.borrow_mut().properties.insert("value".to_string(), Property::Text(value.to_string()));
+    }
+
+    /// Every element below this node, in document order
+    fn elements(&self) -> Vec<Node> {
+        let mut elements = vec![];
+        for child in &self.0.borrow().children {
+            if !child.is_text() {
+                elements.push(child.clone());
+                elements.extend(child.elements());
+            }
+        }
+        elements
+    }
+
+    fn is(&self, other: &Node) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The in-memory DOM, as a backend
+pub struct Dom {
+    body: Node,
+    frame_requested: bool,
+}
+
+impl Dom {
+    fn new() -> Dom {
+        Dom { body: Node::new("body", ""), frame_requested: false }
+    }
+}
+
+impl Backend for Dom {
+    type Node = Node;
+
+    fn root(&self) -> Node {
+        self.body.clone()
+    }
+
+    fn element_by_id(&self, id: &str) -> Node {
+        self.body.elements().into_iter().find(|elem| elem.id() == id)
+            .expect("Patch refers to an element that is not in the document")
+    }
+
+    fn children(&self, parent: &Node) -> Vec<Node> {
+        parent.children()
+    }
+
+    fn create_element(&mut self, tag: &str) -> Node {
+        Node::new(tag, "")
+    }
+
+    fn create_text(&mut self, text: &str) -> Node {
+        Node::new("#text", text)
+    }
+
+    fn set_text(&mut self, node: &Node, text: &str) {
+        node.0.borrow_mut().text = text.to_string();
+    }
+
+    fn insert_before(&mut self, parent: &Node, node: &Node, before: Option<&Node>) {
+        let mut parent = parent.0.borrow_mut();
+        parent.children.retain(|child| !child.is(node));
+        let index = match before {
+            Some(before) => parent.children.iter().position(|child| child.is(before))
+                .expect("Cannot insert before a node that is not a child"),
+            None => parent.children.len(),
+        };
+        parent.children.insert(index, node.clone());
+    }
+
+    fn remove(&mut self, parent: &Node, node: &Node) {
+        parent.0.borrow_mut().children.retain(|child| !child.is(node));
+    }
+
+    fn set_attribute(&mut self, elem: &Node, name: &str, value: &str) {
+        elem.0.borrow_mut().attributes.insert(name.to_string(), value.to_string());
+    }
+
+    fn remove_attribute(&mut self, elem: &Node, name: &str) {
+        elem.0.borrow_mut().attributes.remove(name);
+    }
+
+    fn set_property(&mut self, elem: &Node, name: &str, value: &Property) {
+        elem.0.borrow_mut().properties.insert(name.to_string(), value.clone());
+    }
+
+    fn set_listeners(&mut self, elem: &Node, _id: &str, listeners: &HashMap<String, Listener>) {
+        elem.0.borrow_mut().listeners = listeners.clone();
+    }
+
+    fn value(&self, elem: &Node) -> String {
+        elem.value()
+    }
+
+    fn request_frame(&mut self) {
+        self.frame_requested = true;
+    }
+}
+
+/// Drives the app's render loop against an in-memory DOM
+pub struct Harness {
+    dom: RefCell<Dom>,
+}
+
+impl Harness {
+    /// Takes the `app_setup` function generated by `make_app_setup!`, and renders the first frame.
+    /// Apps don't save their state unless `persist::set_data_dir` gave them somewhere to save it.
+    pub fn new(app_setup: fn()) -> Harness {
+        #[cfg(feature = "persist")]
+        persist::set_data_dir_unless_set(None);
+        app_setup();
+        let mut harness = Harness { dom: RefCell::new(Dom::new()) };
+        harness.frame();
+        harness
+    }
+
+    /// Renders the app if its state changed, like Servo does once per frame
+    pub fn frame(&mut self) {
+        let dom = self.dom.get_mut();
+        dom.frame_requested = false;
+        html::render_frame(dom);
+    }
+
+    /// Waits for the background tasks the app spawned, then renders what they changed
+    pub fn finish_tasks(&mut self) {
+        task::wait();
+        self.frame();
+    }
+
+    /// Moves the clock timers go by ahead, then renders a frame, which runs the timers that became due
+    /// and the futures waiting for them
+    pub fn advance_time(&mut self, by: Duration) {
+        timer::advance(by);
+        self.frame();
+    }
+
+    /// Whether an event asked for a frame since the last one was rendered
+    pub fn frame_requested(&self) -> bool {
+        self.dom.borrow().frame_requested
+    }
+
+    /// The rendered root nodes
+    pub fn roots(&self) -> Vec<Node> {
+        self.dom.borrow().body.children()
+    }
+
+    pub fn find_by_id(&self, id: &str) -> Option<Node> {
+        self.dom.borrow().body.elements().into_iter().find(|elem| elem.id() == id)
+    }
+
+    pub fn find_by_key(&self, key: &str) -> Option<Node> {
+        self.find_by_id(&html::key_id(key))
+    }
+
+    /// Every element with the tag, in document order
+    pub fn find_by_tag(&self, tag: &str) -> Vec<Node> {
+        self.dom.borrow().body.elements().into_iter().filter(|elem| elem.tag() == tag).collect()
+    }
+
+    /// The innermost element whose text is exactly `text`, like the button in `<div><button>+</button></div>`
+    pub fn find_by_text(&self, text: &str) -> Option<Node> {
+        let mut found = self.dom.borrow().body.elements().into_iter().find(|elem| elem.text() == text)?;
+        loop {
+            let inner = found.children().into_iter().find(|child| !child.is_text() && child.text() == text);
+            match inner {
+                Some(elem) => found = elem,
+                None => return Some(found),
+            }
+        }
+    }
+
+    /// Calls the listener the element registered for `event`, with the element's current value.
+    /// Any state changes show up in the DOM after the next `frame`.
+    pub fn fire(&self, elem: &Node, event: &str) {
+        let listener = elem.0.borrow().listeners.get(event).cloned()
+            .unwrap_or_else(|| panic!("Element {} has no listener for {}", elem.id(), event));
+        let event = Event { kind: event.to_string(), target: elem.id(), value: elem.value() };
+        backend::dispatch(&mut *self.dom.borrow_mut(), &listener, &event);
+    }
+}
@@ -0,0 +1,165 @@
+//! Backends display the rendered tree. The diff only decides what has to change, and `apply_patch`
+//! turns that into calls on the backend, so the view layer doesn't depend on any particular DOM.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use diff;
+use diff::Patch;
+use diff::Slot;
+use html;
+use html::Event;
+use html::HtmlElement;
+use html::HtmlNode;
+use html::Listener;
+use html::Property;
+use update;
+
+pub mod headless;
+
+/// A DOM that ion can render into, like Servo's or the in-memory one in `headless`
+pub trait Backend {
+    /// A handle to a node. Clones refer to the same node.
+    type Node: Clone;
+
+    /// The node the root nodes are rendered into, like the document body
+    fn root(&self) -> Self::Node;
+    fn element_by_id(&self, id: &str) -> Self::Node;
+    /// All child nodes of `parent`, including ones ion did not put there
+    fn children(&self, parent: &Self::Node) -> Vec<Self::Node>;
+
+    fn create_element(&mut self, tag: &str) -> Self::Node;
+    fn create_text(&mut self, text: &str) -> Self::Node;
+    fn set_text(&mut self, node: &Self::Node, text: &str);
+    /// Inserts or moves `node` in front of `before`, or to the end of `parent` if there is none
+    fn insert_before(&mut self, parent: &Self::Node, node: &Self::Node, before: Option<&Self::Node>);
+    fn remove(&mut self, parent: &Self::Node, node: &Self::Node);
+
+    fn set_attribute(&mut self, elem: &Self::Node, name: &str, value: &str);
+    fn remove_attribute(&mut self, elem: &Self::Node, name: &str);
+    fn set_property(&mut self, elem: &Self::Node, name: &str, value: &Property);
+    /// Replaces every listener of the element with the id `id`. Events should be passed to `dispatch`.
+    /// This only happens when the element starts or stops listening to an event, since each listener
+    /// runs whatever handler the element has in the latest render.
+    fn set_listeners(&mut self, elem: &Self::Node, id: &str, listeners: &HashMap<String, Listener>);
+    /// The current value of a form control like a textarea, otherwise empty
+    fn value(&self, elem: &Self::Node) -> String;
+
+    /// Asks for `html::render_frame` to be called soon, because the app state might have changed
+    fn request_frame(&mut self);
+}
+
+/// Runs a listener for an event the backend received, then asks for a frame to show what it changed
+pub fn dispatch<B: Backend>(backend: &mut B, listener: &Listener, event: &Event) {
+    update::in_event(|| listener(event));
+    backend.request_frame();
+}
+
+pub(crate) fn apply_patch<B: Backend>(backend: &mut B, patch: &Patch) {
+    match *patch {
+        Patch::Children { parent, old_len, ref removed, ref order } => {
+            let parent = if parent == diff::ROOT { backend.root() } else { backend.element_by_id(parent) };
+
+            // The root may have nodes of its own in front of the ones we rendered
+            let mut old_nodes: Vec<Option<B::Node>> = backend.children(&parent).into_iter().map(Some).collect();
+            let first_rendered = old_nodes.len() - old_len;
+            let mut old_nodes = old_nodes.split_off(first_rendered);
+
+            for &i in removed {
+                backend.remove(&parent, old_nodes[i].as_ref().unwrap());
+            }
+
+            // Place right to left, so the next sibling is always already where it belongs
+            let mut before: Option<B::Node> = None;
+            for slot in order.iter().rev() {
+                let node = match *slot {
+                    Slot::Keep { old, moved, text } => {
+                        let node = old_nodes[old].take().unwrap();
+                        if let Some(text) = text {
+                            backend.set_text(&node, text);
+                        }
+                        if moved {
+                            backend.insert_before(&parent, &node, before.as_ref());
+                        }
+                        node
+                    },
+                    Slot::Create(child) => {
+                        let node = make_tree(backend, child);
+                        backend.insert_before(&parent, &node, before.as_ref());
+                        node
+                    },
+                };
+                before = Some(node);
+            }
+        },
+        Patch::SetClass { id, class } => {
+            let elem = backend.element_by_id(id);
+            backend.set_attribute(&elem, "class", class);
+        },
+        Patch::SetStyle { id, style } => {
+            let elem = backend.element_by_id(id);
+            backend.set_attribute(&elem, "style", style);
+        },
+        Patch::SetAttribute { id, name, value } => {
+            let elem = backend.element_by_id(id);
+            backend.set_attribute(&elem, name, value);
+        },
+        Patch::RemoveAttribute { id, name } => {
+            let elem = backend.element_by_id(id);
+            backend.remove_attribute(&elem, name);
+        },
+        Patch::SetProperty { id, name, ref value } => {
+            let elem = backend.element_by_id(id);
+            backend.set_property(&elem, name, value);
+        },
+        Patch::SetListeners { element } => {
+            let elem = backend.element_by_id(&element.id);
+            backend.set_listeners(&elem, &element.id, &trampolines(element));
+        },
+    }
+}
+
+/// One listener per event the element listens to, which looks up the element's handler when it runs
+fn trampolines(elem: &HtmlElement) -> HashMap<String, Listener> {
+    elem.listeners.keys().map(|kind| {
+        let (id, event_kind) = (elem.id.clone(), kind.clone());
+        let listener: Listener = Rc::new(move |event: &Event| {
+            if let Some(listener) = html::current_listener(&id, &event_kind) {
+                listener(event);
+            }
+        });
+        (kind.clone(), listener)
+    }).collect()
+}
+
+fn make_tree<B: Backend>(backend: &mut B, node: &HtmlNode) -> B::Node {
+    let elem = match *node {
+        HtmlNode::Element(ref elem) => elem,
+        HtmlNode::Memo(ref elem) => elem,
+        HtmlNode::Text(ref text) => return backend.create_text(text),
+        HtmlNode::Fragment(_) | HtmlNode::Component(_) => unreachable!("Fragments and components are expanded before rendering"),
+    };
+
+    let node = backend.create_element(&elem.tag);
+    backend.set_attribute(&node, "id", &elem.id);
+    if !elem.class.is_empty() {
+        backend.set_attribute(&node, "class", &elem.class);
+    }
+    if !elem.style.is_empty() {
+        backend.set_attribute(&node, "style", &elem.style);
+    }
+    for (name, value) in &elem.attributes {
+        backend.set_attribute(&node, name, value);
+    }
+    for (name, value) in &elem.properties {
+        backend.set_property(&node, name, value);
+    }
+    if !elem.listeners.is_empty() {
+        backend.set_listeners(&node, &elem.id, &trampolines(elem));
+    }
+    for child in &elem.children {
+        let child = make_tree(backend, child);
+        backend.insert_before(&node, &child, None);
+    }
+    node
+}
@@ -0,0 +1,395 @@
+//! Components are reusable pieces of the view with state of their own, so it doesn't have to live in
+//! the app state:
+//!
+//! ```ignore
+//! struct Counter { count: u32 }
+//!
+//! impl Component for Counter {
+//!     type Props = String;
+//!     fn create(_: &String) -> Counter { Counter { count: 0 } }
+//!     fn render(&self, label: &String, scope: &Scope<Counter>) -> HtmlNode {
+//!         let scope = scope.clone();
+//!         button().text(format!("{}: {}", label, self.count))
+//!             .on("click", move |_| scope.update(|counter| counter.count += 1))
+//!             .into()
+//!     }
+//! }
+//!
+//! html!{ <div>{component::<Counter>("Clicks".to_string())}</div> }
+//! ```
+//!
+//! An instance is kept for as long as a component of its type is rendered at the same position among
+//! its siblings, or with the same key under the same parent, and dropped after the first frame without it.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+use std::rc::Weak;
+
+use html;
+use html::HtmlNode;
+
+thread_local!(static INSTANCES: RefCell<Registry> = RefCell::new(Registry {
+    instances: HashMap::new(),
+    rendered: vec![],
+    changed: HashSet::new(),
+}));
+thread_local!(static CHANGED: Cell<bool> = Cell::new(false));
+
+pub trait Component: Sized + 'static {
+    /// What the parent passes in on every render
+    type Props: 'static;
+
+    /// Makes the instance, the first time the component is rendered at its position
+    fn create(props: &Self::Props) -> Self;
+    fn render(&self, props: &Self::Props, scope: &Scope<Self>) -> HtmlNode;
+
+    /// Called once the first render is in the DOM
+    fn mounted(&mut self) {}
+    /// Called once every later render is in the DOM
+    fn updated(&mut self) {}
+    /// Called when the component is no longer rendered, right before the instance is dropped
+    fn unmounted(&mut self) {}
+}
+
+/// Lets event handlers change the state of the instance that rendered them
+pub struct Scope<C> {
+    instance: Weak<RefCell<C>>,
+    id: String,
+}
+
+impl<C> Clone for Scope<C> {
+    fn clone(&self) -> Scope<C> { Scope { instance: self.instance.clone(), id: self.id.clone() } }
+}
+
+impl<C: Component> Scope<C> {
+    /// Changes the instance and renders the app again. Does nothing once the instance is unmounted.
+    pub fn update<F: FnOnce(&mut C)>(&self, f: F) {
+        if let Some(instance) = self.instance.upgrade() {
+            f(&mut *instance.borrow_mut());
+            INSTANCES.with(|instances| instances.borrow_mut().changed.insert(self.id.clone()));
+            CHANGED.with(|changed| changed.set(true));
+        }
+    }
+}
+
+/// A component that has not been rendered yet, as it appears in an `HtmlNode` tree
+pub struct Mount {
+    pub(crate) key: Option<String>,
+    /// Renders the instance with the given id, or a new instance that is not kept if there is none
+    pub(crate) render: Box<Fn(Option<&str>) -> HtmlNode>,
+}
+
+pub fn component<C: Component>(props: C::Props) -> HtmlNode {
+    mount::<C>(None, props)
+}
+
+/// Keyed components keep their instance when they move among their siblings. Unlike the keys of
+/// elements, the key only has to be unique among the component's siblings.
+pub fn component_keyed<C: Component, K: ToString>(key: K, props: C::Props) -> HtmlNode {
+    mount::<C>(Some(key.to_string()), props)
+}
+
+fn mount<C: Component>(key: Option<String>, props: C::Props) -> HtmlNode {
+    HtmlNode::Component(Mount {
+        key,
+        render: Box::new(move |id| match id {
+            Some(id) => render_instance::<C>(id, &props),
+            None => C::create(&props).render(&props, &Scope { instance: Weak::new(), id: String::new() }),
+        }),
+    })
+}
+
+/// The id components pass on to the nodes they render, in place of their parent's
+pub(crate) fn component_id(mount: &Mount, parent_id: &str, index: usize) -> String {
+    match mount.key {
+        Some(ref key) => format!("{}.{}-component", parent_id, html::key_id(key)),
+        None => format!("{}.{}-component", parent_id, index),
+    }
+}
+
+fn render_instance<C: Component>(id: &str, props: &C::Props) -> HtmlNode {
+    let existing = INSTANCES.with(|instances| {
+        instances.borrow().instances.get(id)
+            .and_then(|instance| instance.as_any().downcast_ref::<Rc<RefCell<C>>>().cloned())
+    });
+    let created = existing.is_none();
+    let instance = match existing {
+        Some(instance) => instance,
+        None => {
+            let instance = Rc::new(RefCell::new(C::create(props)));
+            // A component of another type that was rendered here is replaced
+            let replaced = INSTANCES.with(|instances| {
+                instances.borrow_mut().instances.insert(id.to_string(), Box::new(instance.clone()))
+            });
+            if let Some(replaced) = replaced {
+                replaced.unmounted();
+            }
+            instance
+        },
+    };
+    let rendered = if created { Rendered::Created } else { Rendered::Updated };
+    INSTANCES.with(|instances| {
+        let mut instances = instances.borrow_mut();
+        if instances.rendered.iter().any(|&(ref rendered, _)| rendered == id) {
+            panic!("The component {} was rendered twice in one frame; keyed components need keys that are unique among their siblings", id);
+        }
+        instances.rendered.push((id.to_string(), rendered));
+    });
+
+    let scope = Scope { instance: Rc::downgrade(&instance), id: id.to_string() };
+    let node = instance.borrow().render(props, &scope);
+    node
+}
+
+/// Whether a component changed its state since the last call
+pub fn take_changed() -> bool {
+    CHANGED.with(|changed| changed.replace(false))
+}
+
+/// How many components were rendered this frame so far, to pass to `rendered_since`
+pub(crate) fn rendered_count() -> usize {
+    INSTANCES.with(|instances| instances.borrow().rendered.len())
+}
+
+/// The ids of the components rendered or kept since `rendered_count` returned `start`
+pub(crate) fn rendered_since(start: usize) -> Vec<String> {
+    INSTANCES.with(|instances| instances.borrow().rendered[start..].iter().map(|&(ref id, _)| id.clone()).collect())
+}
+
+/// Whether any of the components changed their state since they were last rendered
+pub(crate) fn any_changed(ids: &[String]) -> bool {
+    INSTANCES.with(|instances| {
+        let instances = instances.borrow();
+        ids.iter().any(|id| instances.changed.contains(id))
+    })
+}
+
+/// Keeps the instances of components whose last render is reused, without rendering them again
+pub(crate) fn keep(ids: &[String]) {
+    INSTANCES.with(|instances| {
+        let mut instances = instances.borrow_mut();
+        for id in ids {
+            instances.rendered.push((id.clone(), Rendered::Kept));
+        }
+    });
+}
+
+/// Runs the lifecycle hooks once a render is in the DOM, and drops the instances that were not rendered
+pub(crate) fn finish_frame() {
+    let (unmounted, mounted, updated) = INSTANCES.with(|instances| {
+        let mut instances = instances.borrow_mut();
+        let rendered = mem::replace(&mut instances.rendered, vec![]);
+        instances.changed.clear();
+
+        let gone: Vec<String> = {
+            let rendered: HashSet<&String> = rendered.iter().map(|&(ref id, _)| id).collect();
+            instances.instances.keys().filter(|id| !rendered.contains(id)).cloned().collect()
+        };
+        let unmounted: Vec<Box<Instance>> = gone.iter().map(|id| instances.instances.remove(id).unwrap()).collect();
+
+        let mut mounted = vec![];
+        let mut updated = vec![];
+        for (id, rendered) in rendered {
+            if let Some(instance) = instances.instances.get(&id) {
+                match rendered {
+                    Rendered::Created => mounted.push(instance.clone_box()),
+                    Rendered::Updated => updated.push(instance.clone_box()),
+                    Rendered::Kept => {},
+                }
+            }
+        }
+        (unmounted, mounted, updated)
+    });
+
+    // The registry is not borrowed anymore, so hooks are free to render other things
+    for instance in unmounted { instance.unmounted(); }
+    for instance in mounted { instance.mounted(); }
+    for instance in updated { instance.updated(); }
+}
+
+struct Registry {
+    instances: HashMap<String, Box<Instance>>,
+    /// The ids of the instances rendered this frame, in the order they were rendered
+    rendered: Vec<(String, Rendered)>,
+    /// The ids of the instances whose state changed since they were rendered
+    changed: HashSet<String>,
+}
+
+enum Rendered {
+    Created,
+    Updated,
+    /// Not rendered again, because the memo it is in was reused
+    Kept,
+}
+
+/// A component instance with its type erased
+trait Instance {
+    fn as_any(&self) -> &Any;
+    fn clone_box(&self) -> Box<Instance>;
+    fn mounted(&self);
+    fn updated(&self);
+    fn unmounted(&self);
+}
+
+impl<C: Component> Instance for Rc<RefCell<C>> {
+    fn as_any(&self) -> &Any { self }
+    fn clone_box(&self) -> Box<Instance> { Box::new(self.clone()) }
+    fn mounted(&self) { self.borrow_mut().mounted() }
+    fn updated(&self) { self.borrow_mut().updated() }
+    fn unmounted(&self) { self.borrow_mut().unmounted() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::mem;
+
+    use backend::headless::Harness;
+    use html::HtmlNode;
+    use html::RENDER;
+    use html::fragment;
+    use tags::*;
+    use super::Component;
+    use super::Scope;
+    use super::component;
+    use super::component_keyed;
+
+    thread_local!(static VIEW: Cell<fn() -> HtmlNode> = Cell::new(counters));
+    thread_local!(static LABELS: RefCell<Vec<&'static str>> = RefCell::new(vec![]));
+    thread_local!(static HOOKS: RefCell<Vec<String>> = RefCell::new(vec![]));
+
+    struct Counter {
+        label: &'static str,
+        count: u32,
+    }
+
+    impl Component for Counter {
+        type Props = &'static str;
+        fn create(label: &&'static str) -> Counter { Counter { label, count: 0 } }
+        fn render(&self, label: &&'static str, scope: &Scope<Counter>) -> HtmlNode {
+            let scope = scope.clone();
+            button().text(format!("{}: {}", label, self.count))
+                .on("click", move |_| scope.update(|counter| counter.count += 1))
+                .into()
+        }
+        fn mounted(&mut self) { hook("mounted", self.label) }
+        fn updated(&mut self) { hook("updated", self.label) }
+        fn unmounted(&mut self) { hook("unmounted", self.label) }
+    }
+
+    fn hook(name: &str, label: &str) {
+        HOOKS.with(|hooks| hooks.borrow_mut().push(format!("{} {}", name, label)));
+    }
+
+    /// The hooks called since the last call. Instances that are gone are unmounted in no particular order.
+    fn hooks() -> Vec<String> {
+        HOOKS.with(|hooks| mem::replace(&mut *hooks.borrow_mut(), vec![]))
+    }
+
+    fn render() -> Option<HtmlNode> {
+        Some(VIEW.with(|view| view.get())())
+    }
+
+    fn setup() {
+        RENDER.with(|root| root.set(Some(render)));
+    }
+
+    fn show(app: &mut Harness, view: fn() -> HtmlNode, labels: &[&'static str]) {
+        VIEW.with(|current| current.set(view));
+        LABELS.with(|current| *current.borrow_mut() = labels.to_vec());
+        app.frame();
+    }
+
+    fn labels() -> Vec<&'static str> {
+        LABELS.with(|labels| labels.borrow().clone())
+    }
+
+    fn counters() -> HtmlNode {
+        div().children(labels().into_iter().map(|label| component_keyed::<Counter, _>(label, label))).into()
+    }
+
+    fn click(app: &mut Harness, text: &str) {
+        let button = app.find_by_text(text).unwrap_or_else(|| panic!("There is no {:?} button", text));
+        app.fire(&button, "click");
+        app.frame();
+    }
+
+    #[test]
+    fn calls_the_hooks_as_components_come_and_go() {
+        let mut app = Harness::new(setup);
+        show(&mut app, counters, &["a", "b"]);
+        assert_eq!(hooks(), vec!["mounted a", "mounted b"]);
+
+        show(&mut app, counters, &["b", "c"]);
+        assert_eq!(hooks(), vec!["unmounted a", "mounted c", "updated b"]);
+
+        show(&mut app, counters, &["b", "c"]);
+        assert_eq!(hooks(), vec!["updated b", "updated c"]);
+
+        show(&mut app, counters, &["c"]);
+        assert_eq!(hooks(), vec!["unmounted b", "updated c"]);
+    }
+
+    #[test]
+    fn keyed_components_keep_their_state_when_they_move() {
+        let mut app = Harness::new(setup);
+        show(&mut app, counters, &["a", "b"]);
+        click(&mut app, "b: 0");
+        show(&mut app, counters, &["c", "b", "a"]);
+        assert!(app.find_by_text("b: 1").is_some());
+        assert!(app.find_by_text("a: 0").is_some());
+        hooks();
+
+        show(&mut app, counters, &["a", "c"]);
+        show(&mut app, counters, &["a", "c", "b"]);
+        // A component that was gone for a frame starts over
+        assert!(app.find_by_text("b: 0").is_some());
+        assert_eq!(hooks(), vec!["unmounted b", "updated a", "updated c", "mounted b", "updated a", "updated c"]);
+    }
+
+    #[test]
+    fn keys_are_scoped_to_the_parent() {
+        fn two_lists() -> HtmlNode {
+            div()
+                .child(ul().child(component_keyed::<Counter, _>("x", "first")))
+                .child(ul().child(component_keyed::<Counter, _>("x", "second")))
+                .into()
+        }
+        let mut app = Harness::new(setup);
+        show(&mut app, two_lists, &[]);
+        assert_eq!(hooks(), vec!["mounted first", "mounted second"]);
+
+        click(&mut app, "second: 0");
+        assert!(app.find_by_text("first: 0").is_some());
+        assert!(app.find_by_text("second: 1").is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "rendered twice in one frame")]
+    fn rejects_duplicate_keys_among_siblings() {
+        let mut app = Harness::new(setup);
+        show(&mut app, counters, &["a", "a"]);
+    }
+
+    #[test]
+    fn unkeyed_components_are_kept_by_position() {
+        fn unkeyed() -> HtmlNode {
+            fragment(labels().into_iter().map(|label| component::<Counter>(label)))
+        }
+        let mut app = Harness::new(setup);
+        show(&mut app, unkeyed, &["a", "b"]);
+        click(&mut app, "a: 0");
+        hooks();
+
+        // The instance at the first position is kept, and now renders the new props
+        show(&mut app, unkeyed, &["c"]);
+        assert!(app.find_by_text("c: 1").is_some());
+        assert_eq!(hooks(), vec!["unmounted b", "updated a"]);
+    }
+}
@@ -0,0 +1,362 @@
+//! A panel shown next to the app that lists every message the app handled, and renders the state
+//! as it was after any of them. Turn it on with `devtools` in `make_app_setup!`:
+//!
+//! ```ignore
+//! make_app_setup!{ pub fn app_setup() app_thread_state = APP_STATE, render = render, update = update,
+//!                  devtools = cfg!(debug_assertions) }
+//! ```
+//!
+//! The state has to implement `Clone` and `Debug`, and the messages `Debug`. While an earlier point is
+//! shown, the live state is left alone: messages sent from the old UI are dropped, and the ones sent
+//! by tasks, timers and futures wait until the panel goes back to live. Changes made without a
+//! message get an entry of their own. Only the latest `MAX_ENTRIES` entries are kept.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::mem;
+use std::thread::LocalKey;
+
+use component;
+use html::HtmlElement;
+use html::HtmlNode;
+use html::fragment;
+use observable::Observable;
+use tags::*;
+use update;
+
+thread_local!(static TOOLS: RefCell<Option<Box<Any>>> = RefCell::new(None));
+
+/// How many entries are kept before the oldest are forgotten
+const MAX_ENTRIES: usize = 1000;
+/// The most pairs of lines `diff_lines` compares, since it needs a table of that size. Bigger changes
+/// are shown as every old line removed and every new one added.
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+struct Tools<S> {
+    entries: Vec<Entry<S>>,
+    /// The entry shown instead of the live state
+    viewing: Option<usize>,
+    open: bool,
+    /// Whether the panel changed since the last frame
+    changed: bool,
+}
+
+struct Entry<S> {
+    label: String,
+    changed: Vec<&'static str>,
+    state: S,
+    /// The state printed with `{:#?}`, which the diffs are made from
+    printed: String,
+    /// The lines that changed since the entry before, as `(added, line)`
+    diff: Vec<(bool, String)>,
+}
+
+fn with_tools<S: 'static, R, F: FnOnce(&mut Tools<S>) -> R>(f: F) -> R {
+    TOOLS.with(|tools| {
+        let mut tools = tools.borrow_mut();
+        if tools.is_none() {
+            *tools = Some(Box::new(Tools::<S> { entries: vec![], viewing: None, open: true, changed: false }));
+        }
+        let tools = tools.as_mut().unwrap().downcast_mut::<Tools<S>>().expect("The devtools were used with two kinds of state");
+        f(tools)
+    })
+}
+
+impl<S> Tools<S> {
+    fn push(&mut self, entry: Entry<S>) {
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+            // Keeps showing the same entry, unless it was the one forgotten
+            self.viewing = self.viewing.map(|i| i.saturating_sub(1));
+        }
+        self.changed = true;
+    }
+}
+
+/// Changes what the panel shows, and renders again
+fn change<S: 'static, F: FnOnce(&mut Tools<S>)>(f: F) {
+    with_tools::<S, _, _>(|tools| {
+        f(tools);
+        tools.changed = true;
+    })
+}
+
+impl<S: Clone + Debug + Observable> Entry<S> {
+    fn new(label: String, state: &S, previous: Option<&Entry<S>>) -> Entry<S> {
+        let printed = format!("{:#?}", state);
+        let diff = previous.map_or(vec![], |previous| {
+            diff_lines(&previous.printed, &printed).into_iter().map(|(added, line)| (added, line.to_string())).collect()
+        });
+        Entry {
+            label,
+            changed: previous.map_or(vec![], |previous| state.changes().changed_since(previous.state.changes())),
+            state: state.clone(),
+            printed,
+            diff,
+        }
+    }
+}
+
+/// Used by `make_app_setup!` in place of the usual frame when the devtools are on
+pub fn frame<S, M, N>(app_state: &'static LocalKey<RefCell<S>>, render: fn(&S) -> N, update: fn(&mut S, M)) -> Option<HtmlNode>
+    where S: Clone + Debug + Observable + 'static, M: Debug + 'static, N: Into<HtmlNode> {
+    app_state.with(|root| {
+        let mut state = root.borrow_mut();
+        with_tools::<S, _, _>(|tools| {
+            if tools.entries.is_empty() {
+                tools.entries.push(Entry::new("Initial state".to_string(), &*state, None));
+            } else if state.changes().has_changed() {
+                // Changed since the last frame by a task, timer or anything else that is not a message
+                let entry = Entry::new("Changed outside of update".to_string(), &*state, tools.entries.last());
+                tools.push(entry);
+            }
+            if tools.viewing.is_some() {
+                // The rest are applied once the panel goes back to live
+                update::drop_event_messages();
+                return;
+            }
+            while let Some(msg) = update::next_message::<M>() {
+                let label = format!("{:?}", msg);
+                update(&mut *state, msg);
+                let entry = Entry::new(label, &*state, tools.entries.last());
+                tools.push(entry);
+            }
+        });
+    });
+
+    let has_changed = app_state.with(|root| root.borrow().changes().has_changed());
+    let components_changed = component::take_changed();
+    let tools_changed = with_tools::<S, _, _>(|tools| mem::replace(&mut tools.changed, false));
+    if !has_changed && !components_changed && !tools_changed { return None };
+
+    // The tools are not borrowed while rendering, in case the app's render function sends a message
+    let viewing = with_tools::<S, _, _>(|tools| tools.viewing.map(|i| tools.entries[i].state.clone()));
+    let app = app_state.with(|root| {
+        let node: HtmlNode = match viewing {
+            Some(ref state) => render(state).into(),
+            None => render(&*root.borrow()).into(),
+        };
+        root.borrow_mut().changes_mut().finish_frame();
+        node
+    });
+    let panel = with_tools::<S, _, _>(|tools| render_panel(tools));
+    Some(fragment(vec![app, panel.into()]))
+}
+
+fn render_panel<S: Clone + Debug + Observable + 'static>(tools: &Tools<S>) -> HtmlElement {
+    let panel = div().key("ion-devtools")
+        .style("position: fixed; top: 0; right: 0; max-height: 100%; overflow: auto; width: 320px; \
+                background: #222; color: #eee; font: 12px monospace; z-index: 1000");
+    let toggle = button().text(if tools.open { "Hide devtools" } else { "Devtools" })
+        .on("click", |_| change::<S, _>(|tools| tools.open = !tools.open));
+    if !tools.open {
+        return panel.child(toggle);
+    }
+
+    let last = tools.entries.len() - 1;
+    let current = tools.viewing.unwrap_or(last);
+    let controls = div()
+        .child(toggle)
+        .child(button().text("<").disabled(current == 0)
+            .on("click", move |_| change::<S, _>(|tools| tools.viewing = Some(current - 1))))
+        .child(button().text(">").disabled(current == last)
+            .on("click", move |_| change::<S, _>(|tools| tools.viewing = Some(current + 1))))
+        .child(button().text("Live").disabled(tools.viewing.is_none())
+            .on("click", |_| {
+                // Clicks on the old UI since the last frame would otherwise be applied to the live state
+                update::drop_event_messages();
+                change::<S, _>(|tools| tools.viewing = None)
+            }))
+        .child(input().key("ion-devtools-scrub").attr("type", "range").attr("min", 0).attr("max", last)
+            .value(current.to_string()).style("width: 100%")
+            .on("input", |event| {
+                if let Ok(i) = event.value.parse::<usize>() {
+                    change::<S, _>(|tools| tools.viewing = Some(i));
+                }
+            }));
+
+    let entries = ol().attr("start", 0).children(tools.entries.iter().enumerate().map(|(i, entry)| {
+        let label = if entry.changed.is_empty() {
+            entry.label.clone()
+        } else {
+            format!("{} ({})", entry.label, entry.changed.join(", "))
+        };
+        li().text(label)
+            .style(if i == current { "background: #555; cursor: pointer" } else { "cursor: pointer" })
+            .on("click", move |_| change::<S, _>(|tools| tools.viewing = Some(i)))
+    }));
+
+    let diff = pre().children(tools.entries[current].diff.iter().map(|&(added, ref line)| {
+        span().style(if added { "color: #8f8" } else { "color: #f88" })
+            .text(format!("{} {}\n", if added { "+" } else { "-" }, line))
+    }));
+
+    panel.child(controls).child(entries).child(diff)
+}
+
+/// The lines that were removed from `before` and added in `after`, in order, as `(added, line)`
+fn diff_lines<'a>(before: &'a str, after: &'a str) -> Vec<(bool, &'a str)> {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+
+    // A message usually changes a few lines of a long state, so only the part between the lines both
+    // start and end with is compared line by line
+    let prefix = before.iter().zip(&after).take_while(|&(a, b)| a == b).count();
+    let suffix = before[prefix..].iter().rev().zip(after[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+    let before = &before[prefix..before.len() - suffix];
+    let after = &after[prefix..after.len() - suffix];
+    if before.len().saturating_mul(after.len()) > MAX_DIFF_CELLS {
+        return before.iter().map(|&line| (false, line)).chain(after.iter().map(|&line| (true, line))).collect();
+    }
+
+    // common[i][j] is the length of the longest common subsequence of before[i..] and after[j..]
+    let mut common = vec![vec![0; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i] == after[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            i += 1;
+            j += 1;
+        } else if i < before.len() && (j == after.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push((false, before[i]));
+            i += 1;
+        } else {
+            lines.push((true, after[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use backend::headless::Harness;
+    use html::HtmlElement;
+    use tags::*;
+    use update;
+    use super::Entry;
+    use super::MAX_ENTRIES;
+    use super::Tools;
+    use super::diff_lines;
+
+    observable! {
+        #[derive(Clone, Debug)]
+        struct State {
+            count: u32 = 0,
+        }
+    }
+
+    thread_local!(static STATE: RefCell<State> = RefCell::new(State::new()));
+
+    #[derive(Debug)]
+    enum Msg { Add }
+
+    fn update(state: &mut State, msg: Msg) {
+        match msg {
+            Msg::Add => {
+                let count = *state.get_count() + 1;
+                state.set_count(count);
+            },
+        }
+    }
+
+    fn render(state: &State) -> HtmlElement {
+        div()
+            .child(button().text("+").on_message("click", |_| Msg::Add))
+            .child(span().key("count").text(state.get_count().to_string()))
+    }
+
+    make_app_setup!{ pub fn app_setup() app_thread_state = STATE, render = render, update = update, devtools = true }
+
+    fn click(app: &mut Harness, text: &str) {
+        let button = app.find_by_text(text).unwrap_or_else(|| panic!("There is no {:?} button", text));
+        app.fire(&button, "click");
+        app.frame();
+    }
+
+    fn count(app: &Harness) -> String {
+        app.find_by_key("count").unwrap().text()
+    }
+
+    #[test]
+    fn holds_messages_not_sent_by_the_old_ui_until_live() {
+        let mut app = Harness::new(app_setup);
+        click(&mut app, "+");
+        assert_eq!(count(&app), "1");
+
+        click(&mut app, "<");
+        assert_eq!(count(&app), "0");
+        // From the old UI, so dropped
+        click(&mut app, "+");
+        // Like a timer or a task would
+        update::send(Msg::Add);
+        app.frame();
+        assert_eq!(count(&app), "0");
+        assert_eq!(STATE.with(|state| *state.borrow().get_count()), 1);
+
+        click(&mut app, "Live");
+        assert_eq!(count(&app), "2");
+    }
+
+    #[test]
+    fn diff_lines_in_the_middle() {
+        let before = "a\nb\nc\nd\ne";
+        let after = "a\nb\nx\nd\ny\ne";
+        assert_eq!(diff_lines(before, after), vec![(false, "c"), (true, "x"), (true, "y")]);
+    }
+
+    #[test]
+    fn diff_lines_at_the_ends() {
+        assert_eq!(diff_lines("a\nb", "a\nb"), vec![]);
+        assert_eq!(diff_lines("a\nb", "a\nb\nb"), vec![(true, "b")]);
+        assert_eq!(diff_lines("a\nb", "b"), vec![(false, "a")]);
+        assert_eq!(diff_lines("", "a"), vec![(true, "a")]);
+    }
+
+    #[test]
+    fn diff_lines_of_a_long_state() {
+        let before: String = (0..100_000).map(|i| format!("item {}\n", i)).collect();
+        let after = before.replace("item 50000\n", "item 50000 renamed\n");
+        assert_eq!(diff_lines(&before, &after), vec![(false, "item 50000"), (true, "item 50000 renamed")]);
+    }
+
+    #[test]
+    fn diff_lines_of_a_big_change() {
+        let before: String = (0..2000).map(|i| format!("old {}\n", i)).collect();
+        let after: String = (0..2000).map(|i| format!("new {}\n", i)).collect();
+        let lines = diff_lines(&before, &after);
+        assert_eq!(lines.len(), 4000);
+        assert_eq!((lines[0], lines[1999], lines[2000]), ((false, "old 0"), (false, "old 1999"), (true, "new 0")));
+    }
+
+    #[test]
+    fn forgets_the_oldest_entries() {
+        let entry = |i: usize| Entry { label: i.to_string(), changed: vec![], state: (), printed: String::new(), diff: vec![] };
+        let mut tools = Tools { entries: vec![], viewing: None, open: true, changed: false };
+        for i in 0..MAX_ENTRIES {
+            tools.push(entry(i));
+        }
+        tools.viewing = Some(10);
+        tools.push(entry(MAX_ENTRIES));
+        tools.push(entry(MAX_ENTRIES + 1));
+
+        assert_eq!(tools.entries.len(), MAX_ENTRIES);
+        assert_eq!(tools.entries[0].label, "2");
+        assert_eq!(tools.entries[MAX_ENTRIES - 1].label, (MAX_ENTRIES + 1).to_string());
+        assert_eq!(tools.viewing, Some(8));
+    }
+}
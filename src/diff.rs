@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use html::HtmlElement;
+use html::HtmlNode;
+use html::Property;
+
+/// Parent id used for the root nodes, which live directly under the document body
+pub const ROOT: &str = "";
+
+/// A single change to the real DOM. Elements are referred to by their DOM id.
+pub enum Patch<'a> {
+    /// Rearranges the children of `parent`. The last `old_len` child nodes of the parent are the ones
+    /// the previous render put there; `removed` indexes into them, as does every `Slot::Keep`.
+    Children { parent: &'a str, old_len: usize, removed: Vec<usize>, order: Vec<Slot<'a>> },
+    SetClass { id: &'a str, class: &'a str },
+    SetStyle { id: &'a str, style: &'a str },
+    SetAttribute { id: &'a str, name: &'a str, value: &'a str },
+    RemoveAttribute { id: &'a str, name: &'a str },
+    SetProperty { id: &'a str, name: &'a str, value: Property },
+    /// The element started or stopped listening to some event. Which handler runs is looked up when
+    /// the event happens, so new handlers for the same events don't need a patch.
+    SetListeners { element: &'a HtmlElement },
+}
+
+/// What goes in each position of a patched child list
+pub enum Slot<'a> {
+    /// An existing node, which might have to move, or, for text nodes, get new contents
+    Keep { old: usize, moved: bool, text: Option<&'a str> },
+    Create(&'a HtmlNode),
+}
+
+/// Elements are identified by id; text nodes by their position among their siblings
+#[derive(PartialEq, Eq, Hash)]
+enum Identity<'a> {
+    Element(&'a str),
+    Text(usize),
+}
+
+fn identity(node: &HtmlNode, index: usize) -> Identity {
+    match *node {
+        HtmlNode::Element(ref elem) => Identity::Element(&elem.id),
+        HtmlNode::Memo(ref elem) => Identity::Element(&elem.id),
+        HtmlNode::Text(_) => Identity::Text(index),
+        HtmlNode::Fragment(_) | HtmlNode::Component(_) => unreachable!("Fragments and components are expanded before diffing"),
+    }
+}
+
+/// Computes the patches that turn the DOM built from the `old` root nodes into the one described by `new`.
+/// Both trees must have their ids assigned; elements with the same id are the same DOM node.
+pub fn diff<'a>(old: &'a [HtmlNode], new: &'a [HtmlNode]) -> Vec<Patch<'a>> {
+    let mut patches = vec![];
+    diff_children(ROOT, old, new, &mut patches);
+    patches
+}
+
+fn can_reuse(old: &HtmlNode, new: &HtmlNode) -> bool {
+    match (old.element(), new.element()) {
+        (Some(old), Some(new)) => old.id == new.id && old.tag == new.tag,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn diff_element<'a>(old: &'a HtmlElement, new: &'a HtmlElement, patches: &mut Vec<Patch<'a>>) {
+    if old.class != new.class {
+        patches.push(Patch::SetClass { id: &new.id, class: &new.class });
+    }
+    if old.style != new.style {
+        patches.push(Patch::SetStyle { id: &new.id, style: &new.style });
+    }
+
+    for (name, value) in &new.attributes {
+        if old.attributes.get(name) != Some(value) {
+            patches.push(Patch::SetAttribute { id: &new.id, name, value });
+        }
+    }
+    for name in old.attributes.keys() {
+        if !new.attributes.contains_key(name) {
+            patches.push(Patch::RemoveAttribute { id: &new.id, name });
+        }
+    }
+
+    for (name, value) in &new.properties {
+        if old.properties.get(name) != Some(value) {
+            patches.push(Patch::SetProperty { id: &new.id, name, value: value.clone() });
+        }
+    }
+    // A property can't be removed from the DOM object, so put it back to its empty value instead
+    for (name, value) in &old.properties {
+        if !new.properties.contains_key(name) {
+            patches.push(Patch::SetProperty { id: &new.id, name, value: value.cleared() });
+        }
+    }
+
+    if old.listeners.len() != new.listeners.len() || new.listeners.keys().any(|event| !old.listeners.contains_key(event)) {
+        patches.push(Patch::SetListeners { element: new });
+    }
+
+    diff_children(&new.id, &old.children, &new.children, patches);
+}
+
+fn diff_children<'a>(parent: &'a str, old: &'a [HtmlNode], new: &'a [HtmlNode], patches: &mut Vec<Patch<'a>>) {
+    let mut old_identities = HashMap::new();
+    for (i, child) in old.iter().enumerate() {
+        old_identities.insert(identity(child, i), i);
+    }
+
+    let mut matched: Vec<Option<usize>> = vec![None; new.len()];
+    let mut used = vec![false; old.len()];
+    for (i, child) in new.iter().enumerate() {
+        if let Some(&j) = old_identities.get(&identity(child, i)) {
+            if !used[j] && can_reuse(&old[j], child) {
+                matched[i] = Some(j);
+                used[j] = true;
+            }
+        }
+    }
+
+    let removed: Vec<usize> = (0..old.len()).filter(|&j| !used[j]).collect();
+
+    for (i, child) in new.iter().enumerate() {
+        let j = match matched[i] {
+            Some(j) => j,
+            None => continue,
+        };
+        // A memo that was reused is exactly what is in the DOM already
+        if let (&HtmlNode::Memo(ref old_memo), &HtmlNode::Memo(ref new_memo)) = (&old[j], child) {
+            if Rc::ptr_eq(old_memo, new_memo) {
+                continue;
+            }
+        }
+        if let (Some(old_elem), Some(new_elem)) = (old[j].element(), child.element()) {
+            diff_element(old_elem, new_elem, patches);
+        }
+    }
+
+    // Children in the longest run that is already in order stay put, everything else is moved around them
+    let old_order: Vec<usize> = matched.iter().filter_map(|m| *m).collect();
+    let mut stable = longest_increasing_subsequence(&old_order).into_iter();
+
+    let mut changed = !removed.is_empty();
+    let mut order = vec![];
+    for (i, child) in new.iter().enumerate() {
+        let slot = match matched[i] {
+            Some(j) => {
+                let moved = !stable.next().unwrap();
+                let text = match (&old[j], child) {
+                    (&HtmlNode::Text(ref old_text), &HtmlNode::Text(ref new_text)) if old_text != new_text => {
+                        Some(new_text.as_str())
+                    },
+                    _ => None,
+                };
+                changed |= moved || text.is_some();
+                Slot::Keep { old: j, moved, text }
+            },
+            None => {
+                changed = true;
+                Slot::Create(child)
+            },
+        };
+        order.push(slot);
+    }
+
+    if changed {
+        patches.push(Patch::Children { parent, old_len: old.len(), removed, order });
+    }
+}
+
+/// Marks the members of one longest strictly increasing subsequence of `sequence`
+fn longest_increasing_subsequence(sequence: &[usize]) -> Vec<bool> {
+    // tails[k] is the index of the smallest value that ends an increasing run of length k + 1
+    let mut tails: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![None; sequence.len()];
+
+    for (i, &value) in sequence.iter().enumerate() {
+        let pos = match tails.binary_search_by(|&t| sequence[t].cmp(&value)) {
+            Ok(pos) | Err(pos) => pos,
+        };
+        if pos > 0 {
+            previous[i] = Some(tails[pos - 1]);
+        }
+        if pos == tails.len() {
+            tails.push(i);
+        } else {
+            tails[pos] = i;
+        }
+    }
+
+    let mut members = vec![false; sequence.len()];
+    let mut cursor = tails.last().cloned();
+    while let Some(i) = cursor {
+        members[i] = true;
+        cursor = previous[i];
+    }
+    members
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+
+    use backend::headless::Harness;
+    use backend::headless::Node;
+    use html::HtmlNode;
+    use html::RENDER;
+    use html::fragment;
+    use tags::*;
+    use super::longest_increasing_subsequence;
+
+    thread_local!(static VIEW: Cell<fn() -> HtmlNode> = Cell::new(empty));
+    thread_local!(static KEYS: RefCell<Vec<&'static str>> = RefCell::new(vec![]));
+
+    fn empty() -> HtmlNode { fragment(Vec::<HtmlNode>::new()) }
+
+    fn render() -> Option<HtmlNode> {
+        Some(VIEW.with(|view| view.get())())
+    }
+
+    fn setup() {
+        RENDER.with(|root| root.set(Some(render)));
+    }
+
+    /// Renders `view` with `keys`, and marks every node that is already there, so tests can tell
+    /// which nodes were kept
+    fn show(app: &mut Harness, view: fn() -> HtmlNode, keys: &[&'static str]) {
+        for node in app.find_by_tag("li") {
+            node.set_value("kept");
+        }
+        VIEW.with(|current| current.set(view));
+        KEYS.with(|current| *current.borrow_mut() = keys.to_vec());
+        app.frame();
+    }
+
+    fn keyed_list() -> HtmlNode {
+        KEYS.with(|keys| ul().key("list").children(keys.borrow().iter().map(|&key| li().key(key).text(key)))).into()
+    }
+
+    fn items(app: &Harness) -> Vec<String> {
+        app.find_by_tag("li").iter().map(Node::text).collect()
+    }
+
+    fn kept(app: &Harness) -> Vec<String> {
+        app.find_by_tag("li").iter().filter(|node| node.value() == "kept").map(Node::text).collect()
+    }
+
+    #[test]
+    fn reorders_keyed_children_without_recreating_them() {
+        let mut app = Harness::new(setup);
+        show(&mut app, keyed_list, &["a", "b", "c", "d"]);
+        show(&mut app, keyed_list, &["d", "a", "c", "b"]);
+        assert_eq!(items(&app), vec!["d", "a", "c", "b"]);
+        assert_eq!(kept(&app), vec!["d", "a", "c", "b"]);
+    }
+
+    #[test]
+    fn inserts_in_the_middle() {
+        let mut app = Harness::new(setup);
+        show(&mut app, keyed_list, &["a", "c"]);
+        show(&mut app, keyed_list, &["a", "b", "c"]);
+        assert_eq!(items(&app), vec!["a", "b", "c"]);
+        assert_eq!(kept(&app), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn removes_and_moves() {
+        let mut app = Harness::new(setup);
+        show(&mut app, keyed_list, &["a", "b", "c", "d", "e"]);
+        show(&mut app, keyed_list, &["e", "b", "d"]);
+        assert_eq!(items(&app), vec!["e", "b", "d"]);
+        assert_eq!(kept(&app), vec!["e", "b", "d"]);
+        show(&mut app, keyed_list, &[]);
+        assert!(items(&app).is_empty());
+    }
+
+    #[test]
+    fn moves_a_keyed_element_to_another_parent() {
+        fn two_lists() -> HtmlNode {
+            let keys = KEYS.with(|keys| keys.borrow().clone());
+            let (left, right): (Vec<&str>, Vec<&str>) = keys.iter().partition(|key| key.starts_with('l'));
+            div().children(vec![
+                ul().key("left").children(left.into_iter().map(|key| li().key(&key[1..]).text(&key[1..]))),
+                ul().key("right").children(right.into_iter().map(|key| li().key(&key[1..]).text(&key[1..]))),
+            ]).into()
+        }
+        let mut app = Harness::new(setup);
+        show(&mut app, two_lists, &["la", "lb", "rc"]);
+        show(&mut app, two_lists, &["la", "rb", "rc"]);
+        let right = app.find_by_key("right").unwrap();
+        assert_eq!(right.children().iter().map(Node::text).collect::<Vec<_>>(), vec!["b", "c"]);
+        assert_eq!(app.find_by_key("left").unwrap().text(), "a");
+    }
+
+    #[test]
+    fn interleaves_text_and_elements() {
+        fn mixed() -> HtmlNode {
+            let keys = KEYS.with(|keys| keys.borrow().clone());
+            let mut list = p();
+            for key in keys {
+                list = if key.starts_with('#') { list.text(&key[1..]) } else { list.child(li().key(key).text(key)) };
+            }
+            list.into()
+        }
+        let mut app = Harness::new(setup);
+        show(&mut app, mixed, &["#one", "a", "#two", "b"]);
+        assert_eq!(app.roots()[0].text(), "oneatwob");
+        show(&mut app, mixed, &["#three", "b", "#four", "a", "#five"]);
+        assert_eq!(app.roots()[0].text(), "threebfourafive");
+        assert_eq!(kept(&app), vec!["b", "a"]);
+        let children = app.roots()[0].children();
+        assert_eq!(children.iter().map(Node::is_text).collect::<Vec<_>>(), vec![true, false, true, false, true]);
+    }
+
+    #[test]
+    fn renders_fragments_at_the_root() {
+        fn roots() -> HtmlNode {
+            KEYS.with(|keys| fragment(keys.borrow().iter().map(|&key| li().key(key).text(key))))
+        }
+        let mut app = Harness::new(setup);
+        show(&mut app, roots, &["a", "b"]);
+        show(&mut app, roots, &["b", "x", "a"]);
+        assert_eq!(app.roots().iter().map(Node::text).collect::<Vec<_>>(), vec!["b", "x", "a"]);
+        assert_eq!(kept(&app), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn only_registers_listeners_when_the_events_change() {
+        thread_local!(static CLICKED: Cell<u32> = Cell::new(0));
+        fn button_view() -> HtmlNode {
+            let by = KEYS.with(|keys| keys.borrow().len() as u32);
+            button().key("b").text("+").on("click", move |_| CLICKED.with(|clicked| clicked.set(clicked.get() + by))).into()
+        }
+        let mut app = Harness::new(setup);
+        show(&mut app, button_view, &["one"]);
+        let button = app.find_by_key("b").unwrap();
+        app.fire(&button, "click");
+        show(&mut app, button_view, &["one", "two"]);
+        // The listener that was registered first runs the handler from the latest render
+        app.fire(&button, "click");
+        assert_eq!(CLICKED.with(|clicked| clicked.get()), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "keys have to be unique in the whole page")]
+    fn rejects_duplicate_keys() {
+        let mut app = Harness::new(setup);
+        show(&mut app, keyed_list, &["a", "b", "a"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "keys have to be unique in the whole page")]
+    fn rejects_duplicate_keys_under_different_parents() {
+        fn two_lists() -> HtmlNode {
+            let keys = KEYS.with(|keys| keys.borrow().clone());
+            div().child(ul().children(keys.iter().map(|&key| li().key(key).text(key))))
+                .child(ul().children(keys.iter().map(|&key| li().key(key).text(key)))).into()
+        }
+        let mut app = Harness::new(setup);
+        show(&mut app, two_lists, &["a"]);
+    }
+
+    #[test]
+    fn finds_a_longest_increasing_subsequence() {
+        let members = |sequence: &[usize]| -> Vec<usize> {
+            longest_increasing_subsequence(sequence).iter().zip(sequence).filter(|&(&member, _)| member).map(|(_, &value)| value).collect()
+        };
+        assert_eq!(members(&[]), Vec::<usize>::new());
+        assert_eq!(members(&[0, 1, 2]), vec![0, 1, 2]);
+        assert_eq!(members(&[2, 1, 0]).len(), 1);
+        assert_eq!(members(&[3, 0, 1, 4, 2]), vec![0, 1, 2]);
+        assert_eq!(members(&[1, 5, 2, 6, 3, 4]), vec![1, 2, 3, 4]);
+    }
+}
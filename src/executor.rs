@@ -0,0 +1,230 @@
+//! Runs futures on the thread the app runs on, so they can wait for background tasks, channels or
+//! timers and then change the app state:
+//!
+//! ```ignore
+//! button().text("Open").on("click", |_| {
+//!     executor::spawn_local(task::run(|| load("list.json")).then(|items| {
+//!         APP_STATE.with(|state| state.borrow_mut().set_items(items.unwrap_or_default()));
+//!         Ok(())
+//!     }));
+//! })
+//! ```
+//!
+//! Futures are polled right before a frame, once they were spawned or woken up. Waking one up, from
+//! any thread, also calls the waker the backend gave `task::set_waker`, so that frame gets rendered.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::Async;
+use futures::Future;
+use futures::executor::Notify;
+use futures::executor::NotifyHandle;
+use futures::executor::Spawn;
+use futures::executor::spawn;
+
+use task;
+
+thread_local!(static EXECUTOR: RefCell<Option<Executor>> = RefCell::new(None));
+
+type LocalFuture = Box<Future<Item = (), Error = ()>>;
+
+struct Executor {
+    futures: HashMap<usize, Spawn<LocalFuture>>,
+    next_id: usize,
+    ready: Arc<Ready>,
+}
+
+/// The futures that were woken up since they were last polled. Shared with whatever wakes them,
+/// which might be on another thread.
+struct Ready {
+    ids: Mutex<Vec<usize>>,
+}
+
+impl Notify for Ready {
+    fn notify(&self, id: usize) {
+        self.ids.lock().unwrap().push(id);
+        task::wake();
+    }
+}
+
+fn with_executor<R, F: FnOnce(&mut Executor) -> R>(f: F) -> R {
+    EXECUTOR.with(|executor| {
+        let mut executor = executor.borrow_mut();
+        let executor = executor.get_or_insert_with(|| Executor {
+            futures: HashMap::new(),
+            next_id: 0,
+            ready: Arc::new(Ready { ids: Mutex::new(vec![]) }),
+        });
+        f(executor)
+    })
+}
+
+/// Runs `future` until it completes, starting right before the next frame. Errors have to be handled
+/// by the future itself, so its error type is `()`.
+pub fn spawn_local<F: Future<Item = (), Error = ()> + 'static>(future: F) {
+    with_executor(|executor| {
+        let id = executor.next_id;
+        executor.next_id += 1;
+        executor.futures.insert(id, spawn(Box::new(future) as LocalFuture));
+        executor.ready.notify(id);
+    })
+}
+
+/// Whether any spawned future has not completed yet
+pub fn running() -> bool {
+    EXECUTOR.with(|executor| executor.borrow().as_ref().map_or(false, |executor| !executor.futures.is_empty()))
+}
+
+/// Polls the futures that were woken up, until none are left. Used by `html::render_frame`.
+pub(crate) fn run() {
+    loop {
+        let (ready, ids) = with_executor(|executor| {
+            let ids = mem::replace(&mut *executor.ready.ids.lock().unwrap(), vec![]);
+            (executor.ready.clone(), ids)
+        });
+        if ids.is_empty() {
+            return;
+        }
+        let handle = NotifyHandle::from(ready);
+        for id in ids {
+            // Not borrowed while polling, so the future can spawn others
+            let future = with_executor(|executor| executor.futures.remove(&id));
+            let mut future = match future {
+                Some(future) => future,
+                // Woken up more than once, and already done
+                None => continue,
+            };
+            match future.poll_future_notify(&handle, id) {
+                Ok(Async::NotReady) => with_executor(|executor| { executor.futures.insert(id, future); }),
+                Ok(Async::Ready(())) | Err(()) => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::Async;
+    use futures::Future;
+    use futures::future;
+
+    use backend::headless::Harness;
+    use html::HtmlNode;
+    use html::RENDER;
+    use task;
+    use timer;
+    use super::running;
+    use super::spawn_local;
+
+    fn nothing() -> Option<HtmlNode> { None }
+
+    fn setup() {
+        RENDER.with(|root| root.set(Some(nothing)));
+    }
+
+    /// Where the futures in a test leave what they got
+    fn slot<T>() -> (Rc<RefCell<Option<T>>>, Rc<RefCell<Option<T>>>) {
+        let slot = Rc::new(RefCell::new(None));
+        (slot.clone(), slot)
+    }
+
+    #[test]
+    fn waits_for_a_task() {
+        let mut app = Harness::new(setup);
+        let (result, filled) = slot();
+        spawn_local(task::run(|| 6 * 7).then(move |answer| {
+            *filled.borrow_mut() = Some(answer.ok());
+            Ok(())
+        }));
+        assert!(running());
+
+        app.finish_tasks();
+        assert_eq!(*result.borrow(), Some(Some(42)));
+        assert!(!running());
+    }
+
+    #[test]
+    fn sees_a_task_that_panicked_as_canceled() {
+        let mut app = Harness::new(setup);
+        let (result, filled) = slot();
+        spawn_local(task::run(|| -> u32 { panic!("while loading") }).then(move |answer| {
+            *filled.borrow_mut() = Some(answer.is_err());
+            Ok(())
+        }));
+        app.finish_tasks();
+        assert_eq!(*result.borrow(), Some(true));
+    }
+
+    #[test]
+    fn waits_for_a_delay() {
+        let mut app = Harness::new(setup);
+        let (result, filled) = slot();
+        spawn_local(timer::delay(Duration::from_millis(100)).then(move |_| {
+            *filled.borrow_mut() = Some(());
+            Ok(())
+        }));
+
+        app.advance_time(Duration::from_millis(99));
+        assert!(result.borrow().is_none());
+        app.advance_time(Duration::from_millis(1));
+        assert!(result.borrow().is_some());
+    }
+
+    #[test]
+    fn chains_a_task_and_a_delay() {
+        let mut app = Harness::new(setup);
+        let (result, filled) = slot();
+        spawn_local(task::run(|| "Saved".to_string())
+            .and_then(|saved| timer::delay(Duration::from_secs(1)).map(move |_| saved))
+            .then(move |saved| {
+                *filled.borrow_mut() = saved.ok();
+                Ok(())
+            }));
+
+        app.finish_tasks();
+        assert!(result.borrow().is_none());
+        app.advance_time(Duration::from_secs(1));
+        assert_eq!(*result.borrow(), Some("Saved".to_string()));
+    }
+
+    #[test]
+    fn only_polls_futures_that_were_woken_up() {
+        let mut app = Harness::new(setup);
+        let polls = Rc::new(Cell::new(0));
+        {
+            let polls = polls.clone();
+            spawn_local(future::poll_fn(move || -> Result<Async<()>, ()> {
+                polls.set(polls.get() + 1);
+                Ok(Async::NotReady)
+            }));
+        }
+        app.frame();
+        app.frame();
+        assert_eq!(polls.get(), 1);
+        assert!(running());
+    }
+
+    #[test]
+    fn futures_can_spawn_futures() {
+        let mut app = Harness::new(setup);
+        let (result, filled) = slot();
+        spawn_local(future::lazy(move || {
+            spawn_local(future::lazy(move || {
+                *filled.borrow_mut() = Some(());
+                Ok(())
+            }));
+            Ok(())
+        }));
+        app.frame();
+        assert!(result.borrow().is_some());
+    }
+}
@@ -0,0 +1,212 @@
+//! Undo and redo, by keeping clones of the state from before each change. Changes that leave the
+//! state as it was, like the messages arrow keys send, are not recorded:
+//!
+//! ```ignore
+//! thread_local!(static HISTORY: RefCell<History<AppState>> = RefCell::new(History::new(100)));
+//!
+//! fn update(state: &mut AppState, msg: Msg) {
+//!     HISTORY.with(|history| {
+//!         let mut history = history.borrow_mut();
+//!         match msg {
+//!             Msg::Undo => { history.undo(state); },
+//!             Msg::Redo => { history.redo(state); },
+//!             Msg::Rename(name) => {
+//!                 // Typing a name is undone all at once
+//!                 history.record_coalesced(state, "rename", |state| state.set_name(name));
+//!             },
+//!         }
+//!     })
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use observable::Changes;
+use observable::Observable;
+
+pub struct History<S> {
+    undo: VecDeque<S>,
+    redo: Vec<S>,
+    depth: usize,
+    coalesce_within: Duration,
+    /// The key and time of the last coalesced change, while more changes can be merged into it
+    last: Option<(String, Instant)>,
+}
+
+impl<S: Observable + Clone + PartialEq> History<S> {
+    /// Keeps up to `depth` changes to undo
+    pub fn new(depth: usize) -> History<S> {
+        History {
+            undo: VecDeque::new(),
+            redo: vec![],
+            depth,
+            coalesce_within: Duration::from_secs(1),
+            last: None,
+        }
+    }
+
+    /// How long after a coalesced change another one with the same key is merged into it. One second by default.
+    pub fn coalesce_within(mut self, window: Duration) -> History<S> {
+        self.coalesce_within = window;
+        self
+    }
+
+    /// Makes the change, so that it can be undone. Nothing is recorded if the state is equal to what
+    /// it was before.
+    pub fn record<R, F: FnOnce(&mut S) -> R>(&mut self, state: &mut S, change: F) -> R {
+        let before = state.clone();
+        let result = change(state);
+        if *state != before {
+            self.last = None;
+            self.push(before);
+        }
+        result
+    }
+
+    /// Like `record`, but changes with the same key in quick succession are undone together, like
+    /// the keystrokes of one edit
+    pub fn record_coalesced<K: ToString, R, F: FnOnce(&mut S) -> R>(&mut self, state: &mut S, key: K, change: F) -> R {
+        let before = state.clone();
+        let result = change(state);
+        if *state == before {
+            return result;
+        }
+
+        let key = key.to_string();
+        let now = Instant::now();
+        let merge = match self.last {
+            Some((ref last, time)) => *last == key && now.duration_since(time) < self.coalesce_within,
+            None => false,
+        };
+        if merge {
+            self.redo.clear();
+        } else {
+            self.push(before);
+        }
+        // Measured from the latest change, so a steady stream of edits stays one entry
+        self.last = Some((key, now));
+        result
+    }
+
+    fn push(&mut self, before: S) {
+        self.redo.clear();
+        self.push_undo(before);
+    }
+
+    /// Forgets the oldest change once there are more than `depth`
+    fn push_undo(&mut self, snapshot: S) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.undo.len() == self.depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(snapshot);
+    }
+
+    /// Returns false if there is nothing to undo
+    pub fn undo(&mut self, state: &mut S) -> bool {
+        self.last = None;
+        match self.undo.pop_back() {
+            Some(snapshot) => {
+                self.redo.push(state.clone());
+                Changes::restore(state, &snapshot);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns false if there is nothing to redo
+    pub fn redo(&mut self, state: &mut S) -> bool {
+        self.last = None;
+        match self.redo.pop() {
+            Some(snapshot) => {
+                let current = state.clone();
+                self.push_undo(current);
+                Changes::restore(state, &snapshot);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool { !self.undo.is_empty() }
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    observable! {
+        #[derive(Clone, Debug, PartialEq)]
+        struct State {
+            name: String = String::new(),
+            count: u32 = 0,
+        }
+    }
+
+    #[test]
+    fn undoes_and_redoes_recorded_changes() {
+        let mut state = State::new();
+        let mut history = History::new(10);
+        history.record(&mut state, |state| state.set_count(1));
+        history.record(&mut state, |state| state.set_count(2));
+
+        assert!(history.undo(&mut state));
+        assert_eq!(*state.get_count(), 1);
+        assert!(history.redo(&mut state));
+        assert_eq!(*state.get_count(), 2);
+        assert!(!history.redo(&mut state));
+    }
+
+    #[test]
+    fn skips_changes_that_leave_the_state_as_it_was() {
+        let mut state = State::new();
+        let mut history = History::new(10);
+        history.record(&mut state, |state| state.set_count(1));
+        history.undo(&mut state);
+
+        history.record(&mut state, |state| state.set_count(0));
+        history.record_coalesced(&mut state, "name", |state| state.set_name(String::new()));
+        assert!(!history.can_undo());
+        // Nothing was changed, so there is still something to redo
+        assert!(history.can_redo());
+    }
+
+    #[test]
+    fn coalesces_changes_with_the_same_key() {
+        let mut state = State::new();
+        let mut history = History::new(10);
+        history.record_coalesced(&mut state, "name", |state| state.set_name("M".to_string()));
+        history.record_coalesced(&mut state, "name", |state| state.set_name("M".to_string()));
+        history.record_coalesced(&mut state, "name", |state| state.set_name("Milk".to_string()));
+        history.record_coalesced(&mut state, "count", |state| state.set_count(1));
+
+        history.undo(&mut state);
+        assert_eq!((state.get_name().as_str(), *state.get_count()), ("Milk", 0));
+        history.undo(&mut state);
+        assert_eq!(state.get_name(), "");
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn forgets_the_oldest_changes_past_its_depth() {
+        let mut state = State::new();
+        let mut history = History::new(2);
+        for count in 1..4 {
+            history.record(&mut state, |state| state.set_count(count));
+        }
+        while history.undo(&mut state) {}
+        assert_eq!(*state.get_count(), 1);
+    }
+}
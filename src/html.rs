@@ -1,191 +1,497 @@
-use std::ops::Deref;
 use std::rc::Rc;
 use std::cell::Cell;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 
-use servo::script::dom::document::Document;
-use servo::script::dom::bindings::str::DOMString;
-use servo::script::dom::eventtarget::EventTarget;
-use servo::script::dom::bindings::codegen::Bindings::DocumentBinding::DocumentMethods;
-use servo::script::dom::bindings::codegen::Bindings::ElementBinding::ElementMethods;
-use servo::script::dom::bindings::inheritance::Castable;
-use servo::script::dom::node::Node;
-use servo::script::dom::bindings::codegen::Bindings::NodeBinding::NodeMethods;
-use servo::script::dom::bindings::root::DomRoot;
-use servo::script::dom::bindings::codegen::Bindings::DocumentBinding::ElementCreationOptions;
-use servo::script::dom::element::Element;
-use servo::script::script_thread::ION_APPLICATION_FRAME_CALLBACK;
+use backend;
+use backend::Backend;
+use component;
+use component::Mount;
+use diff;
+use executor;
+use memo;
+use task;
+use timer;
+use update;
 
-pub use servo::script::dom::eventtarget::RustEventHandler;
+thread_local!(pub static RENDER: Cell<Option<fn()->Option<HtmlNode>>> = Cell::new(None));
+thread_local!(static RENDERED_TREE: RefCell<Option<Vec<HtmlNode>>> = RefCell::new(None));
 
-thread_local!(pub static RENDER: Cell<Option<fn()->Option<HtmlElement>>> = Cell::new(None));
+/// What a listener is told about the event it handles. Handlers get this instead of the backend's DOM,
+/// so they run the same against every backend.
+pub struct Event {
+    /// The event name, like "click" or "input"
+    pub kind: String,
+    /// The DOM id of the element the listener is attached to
+    pub target: String,
+    /// The current value of the target if it is a form control like a textarea, otherwise empty
+    pub value: String,
+}
 
-fn ds<T>(str: T) -> DOMString where T: ToString { DOMString::from_string(str.to_string()) }
+pub type Listener = Rc<Fn(&Event)>;
 
-pub struct HtmlElement {
-    id: String,
-    tag: String,
-    text: String,
-    class: String,
-    style: String,
-    listeners: HashMap<String, RustEventHandler>,
-    children: Vec<HtmlElement>
+/// A value that has to be set on the live DOM object rather than as an attribute, like `value` or `checked`
+#[derive(Clone, PartialEq, Debug)]
+pub enum Property {
+    Text(String),
+    Bool(bool),
 }
 
-impl fmt::Debug for HtmlElement {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        (self.id.clone(), self.tag.clone()).fmt(f)
+impl Property {
+    pub(crate) fn cleared(&self) -> Property {
+        match *self {
+            Property::Text(_) => Property::Text(String::new()),
+            Property::Bool(_) => Property::Bool(false),
+        }
     }
 }
 
-impl HtmlElement {
-    fn gen_id() -> u32 {
-        thread_local!(static ID_COUNTER: RefCell<u32> = RefCell::new(0));
-        ID_COUNTER.with(|root| {
-            let val = *root.borrow() + 1;
-            *root.borrow_mut() = val;
-            val
-        })
-    }
+impl From<String> for Property {
+    fn from(value: String) -> Property { Property::Text(value) }
+}
+
+impl<'a> From<&'a str> for Property {
+    fn from(value: &'a str) -> Property { Property::Text(value.to_string()) }
+}
 
-    pub fn get_dom_element_value(id: &String, doc: &Document) -> String {
-        use servo::script::dom::htmltextareaelement::HTMLTextAreaElement;
-        use servo::script::dom::bindings::codegen::Bindings::HTMLTextAreaElementBinding::HTMLTextAreaElementMethods;
+impl From<bool> for Property {
+    fn from(value: bool) -> Property { Property::Bool(value) }
+}
 
-        let elem_ptr = doc.GetElementById(ds(id)).unwrap();
-        elem_ptr.deref().downcast::<HTMLTextAreaElement>()
-            .expect("Cannot get element value on non-textarea element")
-            .Value().to_string()
-    }
+pub enum HtmlNode {
+    Element(HtmlElement),
+    Text(String),
+    /// Nodes rendered in place of the fragment, without a wrapper element. Fragments are spliced into
+    /// their parent when the tree is rendered, so the diff never sees them.
+    Fragment(Vec<HtmlNode>),
+    /// Replaced by what the component renders when the tree is rendered, made with `component::component`
+    Component(Mount),
+    /// A subtree made by `memo::memo`, which is shared with the previous render while it is unchanged
+    Memo(Rc<HtmlElement>),
+}
 
-    pub fn try_set_dom_element_value(id: &String, doc: &Document, value: String) {
-        use servo::script::dom::htmltextareaelement::HTMLTextAreaElement;
-        use servo::script::dom::bindings::codegen::Bindings::HTMLTextAreaElementBinding::HTMLTextAreaElementMethods;
+impl From<HtmlElement> for HtmlNode {
+    fn from(elem: HtmlElement) -> HtmlNode { HtmlNode::Element(elem) }
+}
 
-        let elem_ptr = doc.GetElementById(ds(id)).unwrap();
-        if let Some(elem) = elem_ptr.deref().downcast::<HTMLTextAreaElement>() {
-            elem.SetValue(ds(value))
+impl HtmlNode {
+    /// The element, for nodes that are elements once they are expanded
+    pub(crate) fn element(&self) -> Option<&HtmlElement> {
+        match *self {
+            HtmlNode::Element(ref elem) => Some(elem),
+            HtmlNode::Memo(ref elem) => Some(elem),
+            _ => None,
         }
     }
+}
 
-    pub fn new<T: ToString, U: ToString, V: ToString, W: ToString, X: ToString>(unique_key: Option<T>, tag: U, text: V, class: W, style: X,
-                                                      listeners: HashMap<String, RustEventHandler>,
-                                                      children: Vec<HtmlElement>) -> HtmlElement {
-        let id = match unique_key {
-            Some(k) => format!("unique_key_{}", k.to_string()),
-            _ => Self::gen_id().to_string(),
-        };
-        HtmlElement {
-            id,
-            tag: tag.to_string(),
-            text: text.to_string(),
-            class: class.to_string(),
-            style: style.to_string(),
-            listeners,
-            children,
+pub fn fragment<T: Into<HtmlNode>, I: IntoIterator<Item=T>>(children: I) -> HtmlNode {
+    HtmlNode::Fragment(children.into_iter().map(Into::into).collect())
+}
+
+/// Splices fragments and rendered components into the list of nodes they are in, and gives the elements
+/// their ids. `index` counts the nodes of the parent. The children of a fragment are counted as if they
+/// were the parent's own, while a component takes up one index, whatever it renders to.
+fn expand(nodes: Vec<HtmlNode>, parent_id: &str, index: &mut usize, out: &mut Vec<HtmlNode>) {
+    for node in nodes {
+        match node {
+            HtmlNode::Fragment(children) => expand(children, parent_id, index, out),
+            HtmlNode::Component(mount) => {
+                let id = component::component_id(&mount, parent_id, *index);
+                *index += 1;
+                let rendered = (mount.render)(Some(&id));
+                expand(vec![rendered], &id, &mut 0, out);
+            },
+            HtmlNode::Element(mut elem) => {
+                elem.assign_ids(parent_id, *index);
+                *index += 1;
+                out.push(HtmlNode::Element(elem));
+            },
+            // Already expanded, with ids that don't depend on where it is
+            HtmlNode::Memo(elem) => {
+                *index += 1;
+                out.push(HtmlNode::Memo(elem));
+            },
+            text => {
+                *index += 1;
+                out.push(text);
+            },
         }
     }
+}
 
-    fn render_to_dom_as_root(&self, doc: &Document) {
-        let body_collection = doc.GetElementsByTagName(ds("body"));
-        let body_ptr = body_collection.elements_iter().last().unwrap();
-        {
-            let body_node: &Node = body_ptr.deref().upcast::<Node>();
-            let new_node = &DomRoot::upcast(self.make_tree(doc));
+/// Renders the app if its state changed since the last frame, and brings what the backend displays up to date
+pub fn render_frame<B: Backend>(backend: &mut B) {
+    timer::fire();
+    task::finish();
+    executor::run();
+    let node = match RENDER.with(|root| (root.get().expect("Cannot render a frame before html::RENDER is set"))()) {
+        Some(node) => node,
+        None => return,
+    };
+    let mut roots = vec![];
+    expand(vec![node], "ion", &mut 0, &mut roots);
+    check_keys(&roots, &mut HashMap::new());
 
-            match body_node.GetFirstChild() {
-                Some(ref child) => body_node.ReplaceChild(new_node, child).unwrap(),
-                None => body_node.AppendChild(new_node).unwrap(),
+    RENDERED_TREE.with(|rendered| {
+        let mut rendered = rendered.borrow_mut();
+        {
+            let old: &[HtmlNode] = match *rendered {
+                Some(ref old) => old,
+                None => &[],
             };
+            for patch in diff::diff(old, &roots) {
+                backend::apply_patch(backend, &patch);
+            }
         }
-    }
+        *rendered = Some(roots);
+    });
+    component::finish_frame();
+    memo::finish_frame();
+}
 
-    fn make_tree(&self, doc: &Document) -> DomRoot<Element> {
-        let has_valid_elem = match doc.GetElementById(ds(self.id.clone())) {
-            Some(ref dom_elem) if dom_elem.deref().TagName().to_string() == self.tag.to_uppercase() => true,
-            _ => false
-        };
-        let dom_elem: DomRoot<Element> = if has_valid_elem {
-            let elem_ptr = doc.GetElementById(ds(self.id.clone())).unwrap();
-            Self::try_set_dom_element_value(&self.id, doc, self.text.clone());
+/// Keys become element ids, so two elements with the same key anywhere in the page would be two DOM
+/// nodes with the same id, and only one of them could ever be found again. `seen` maps the keys found
+/// so far to the id of the element they were found under.
+fn check_keys<'a>(nodes: &'a [HtmlNode], seen: &mut HashMap<&'a str, &'a str>) {
+    for node in nodes {
+        if let Some(elem) = node.element() {
+            if let Some(ref key) = elem.key {
+                if let Some(first) = seen.insert(key, &elem.id) {
+                    panic!("Two elements have the key {:?} (the first one is in {}); keys have to be unique in the whole page, since they become element ids", key, first);
+                }
+            }
+            check_keys(&elem.children, seen);
+        }
+    }
+}
 
-            {
-                let node: &EventTarget = elem_ptr.upcast::<EventTarget>();
-                node.remove_all_listeners();
+/// The handler the element with the id has for the event in the latest render. The listeners given
+/// to the backend call this, so they can stay registered while the handlers change every render.
+pub(crate) fn current_listener(id: &str, kind: &str) -> Option<Listener> {
+    fn find(nodes: &[HtmlNode], id: &str, kind: &str) -> Option<Listener> {
+        for node in nodes {
+            if let Some(elem) = node.element() {
+                if elem.id == id {
+                    return elem.listeners.get(kind).cloned();
+                }
+                if let Some(listener) = find(&elem.children, id, kind) {
+                    return Some(listener);
+                }
             }
+        }
+        None
+    }
+    RENDERED_TREE.with(|rendered| rendered.borrow().as_ref().and_then(|roots| find(roots, id, kind)))
+}
 
-            elem_ptr
-        } else {
-            doc.CreateElement(DOMString::from_string(self.tag.clone()),
-                                   unsafe { &ElementCreationOptions::empty(doc.window().get_cx()) }).unwrap()
-        };
+/// The id of the element with the key
+pub(crate) fn key_id(key: &str) -> String {
+    format!("unique_key_{}", key)
+}
 
-        dom_elem.deref().SetId(ds(self.id.clone()));
-        dom_elem.deref().SetAttribute(ds("style"), ds(self.style.clone())).unwrap();
-        dom_elem.deref().SetClassName(ds(self.class.clone()));
-        dom_elem.deref().upcast::<Node>().SetTextContent(Some(ds(self.text.clone())));
+pub struct HtmlElement {
+    pub(crate) id: String,
+    pub(crate) key: Option<String>,
+    pub(crate) tag: String,
+    pub(crate) class: String,
+    pub(crate) style: String,
+    pub(crate) attributes: HashMap<String, String>,
+    pub(crate) properties: HashMap<String, Property>,
+    pub(crate) listeners: HashMap<String, Listener>,
+    pub(crate) children: Vec<HtmlNode>
+}
 
-        for (event, listener) in &self.listeners {
-            let node: &EventTarget = dom_elem.upcast::<EventTarget>();
-            node.add_event_handler_rust(ds(event), listener.clone());
+impl fmt::Debug for HtmlElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (self.id.clone(), self.tag.clone()).fmt(f)
+    }
+}
+
+impl HtmlElement {
+    /// Usually built through the functions in `tags`, like `div().class("row").child(p().text("Hi"))`
+    pub fn new<T: ToString>(tag: T) -> HtmlElement {
+        HtmlElement {
+            id: String::new(),
+            key: None,
+            tag: tag.to_string(),
+            class: String::new(),
+            style: String::new(),
+            attributes: HashMap::new(),
+            properties: HashMap::new(),
+            listeners: HashMap::new(),
+            children: vec![],
         }
-        for child in &self.children {
-            let dom_child = child.make_tree(doc);
-            dom_elem.upcast::<Node>().AppendChild(&DomRoot::upcast(dom_child)).unwrap();
+    }
+
+    /// Unkeyed elements are identified by their path from the nearest keyed ancestor (sibling index
+    /// and tag at each level), so they keep their DOM node, focus and scroll position across renders
+    fn assign_ids(&mut self, parent_id: &str, index: usize) {
+        if self.key.is_none() {
+            self.id = format!("{}.{}-{}", parent_id, index, self.tag);
         }
-        dom_elem
+        self.expand_children();
     }
 
+    pub(crate) fn expand_children(&mut self) {
+        let children = ::std::mem::replace(&mut self.children, vec![]);
+        expand(children, &self.id, &mut 0, &mut self.children);
+    }
+
+    /// Unkeyed elements only get an id once they are rendered
     pub fn get_id(&self) -> String {
         self.id.clone()
     }
-    pub fn add_listener<T: ToString>(&mut self, event: Vec<T>, listener: RustEventHandler) {
+    /// `id`, `class` and `style` are managed by their own fields, and should not be set this way
+    pub fn set_attribute<T: ToString, U: ToString>(&mut self, name: T, value: U) {
+        self.attributes.insert(name.to_string(), value.to_string());
+    }
+    pub fn set_property<T: ToString, U: Into<Property>>(&mut self, name: T, value: U) {
+        self.properties.insert(name.to_string(), value.into());
+    }
+    pub fn add_listener<T: ToString>(&mut self, event: Vec<T>, listener: Listener) {
         for e in event {
             self.listeners.insert(e.to_string(), listener.clone());
         }
     }
-}
 
-fn frame_callback(doc: &Document) {
-    RENDER.with(|root| match (root.get().expect("Frame callback should not be set before html::RENDER is"))() {
-        Some(elem) => elem.render_to_dom_as_root(doc),
-        None => {}
-    });
-}
-
-pub fn app_main(doc: &Document) {
-    let window = doc.window();
-    window.deref().upcast::<EventTarget>().add_event_handler_rust(ds("load"), RustEventHandler {
-        handler: Rc::new(  |_, _| {
-            ION_APPLICATION_FRAME_CALLBACK.with(|root| {
-                assert!(root.get().is_none());
-                root.set(Some(frame_callback))
-            });
-        })
-    });
+    /// Keyed elements are matched by key instead of position when diffing, and get a predictable id.
+    /// Since the key becomes the id, it has to be unique in the whole page, not only among siblings.
+    pub fn key<T: ToString>(mut self, key: T) -> HtmlElement {
+        let key = key.to_string();
+        self.id = key_id(&key);
+        self.key = Some(key);
+        self
+    }
+    /// Adds a text node after the current children
+    pub fn text<T: ToString>(mut self, text: T) -> HtmlElement {
+        self.children.push(HtmlNode::Text(text.to_string()));
+        self
+    }
+    pub fn class<T: ToString>(mut self, class: T) -> HtmlElement {
+        self.class = class.to_string();
+        self
+    }
+    pub fn style<T: ToString>(mut self, style: T) -> HtmlElement {
+        self.style = style.to_string();
+        self
+    }
+    pub fn attr<T: ToString, U: ToString>(mut self, name: T, value: U) -> HtmlElement {
+        self.set_attribute(name, value);
+        self
+    }
+    pub fn prop<T: ToString, U: Into<Property>>(mut self, name: T, value: U) -> HtmlElement {
+        self.set_property(name, value);
+        self
+    }
+    pub fn on<T: ToString, F: Fn(&Event) + 'static>(self, event: T, handler: F) -> HtmlElement {
+        self.on_events(vec![event], handler)
+    }
+    /// Registers one handler for several events, since closures can't be cloned
+    pub fn on_events<T: ToString, F: Fn(&Event) + 'static>(mut self, events: Vec<T>, handler: F) -> HtmlElement {
+        self.add_listener(events, Rc::new(handler));
+        self
+    }
+    /// Sends the message the handler returns to the app's `update` function
+    pub fn on_message<T: ToString, M: 'static, F: Fn(&Event) -> M + 'static>(self, event: T, message: F) -> HtmlElement {
+        self.on_events_message(vec![event], message)
+    }
+    pub fn on_events_message<T: ToString, M: 'static, F: Fn(&Event) -> M + 'static>(self, events: Vec<T>, message: F) -> HtmlElement {
+        self.on_events(events, move |event| update::send(message(event)))
+    }
+    pub fn child<T: Into<HtmlNode>>(mut self, child: T) -> HtmlElement {
+        self.children.push(child.into());
+        self
+    }
+    pub fn children<T: Into<HtmlNode>, I: IntoIterator<Item=T>>(mut self, children: I) -> HtmlElement {
+        self.children.extend(children.into_iter().map(Into::into));
+        self
+    }
+    pub fn append<C: Child>(self, child: C) -> HtmlElement {
+        child.append_to(self)
+    }
 }
 
+/// Sets up an app, with the options that follow `render` in any order, except that `devtools` goes after `update`:
+///
+/// ```ignore
+/// make_app_setup!{ pub fn app_setup() app_thread_state = APP_STATE, render = render,
+///                  // Messages sent by event handlers are passed to `update` before rendering
+///                  update = update,
+///                  // The devtools panel is shown when this is true. See `devtools`.
+///                  devtools = cfg!(debug_assertions),
+///                  // Restores the state before the first frame, and saves it when it changes. See `persist`.
+///                  persist = Persist::new("todo", 1) }
+/// ```
 #[macro_export]
 macro_rules! make_app_setup {
-    (pub fn $app_setup_name:ident() app_thread_state = $app_state_thread_local_name:ident, render = $render:ident) => {
-        fn _do_not_use_make_app_setup_twice_in_one_file() -> Option<HtmlElement> {
-            let has_changed = $app_state_thread_local_name.with(|root| {
-                let val = root.borrow().has_changed;
-                root.borrow_mut().has_changed = false;
-                val
-            });
-            if !has_changed { return None };
-            Some($app_state_thread_local_name.with(|state| $render(&*state.borrow())))
+    (pub fn $app_setup_name:ident() app_thread_state = $app_state_thread_local_name:ident, render = $render:ident $($options:tt)*) => {
+        make_app_setup!(@options [$app_setup_name $app_state_thread_local_name $render] [] [] [] $($options)*);
+    };
+
+    (@options $setup:tt $update:tt $devtools:tt $persist:tt $(,)*) => {
+        make_app_setup!(@setup $setup $update $devtools $persist);
+    };
+    (@options $setup:tt [] $devtools:tt $persist:tt, update = $update:ident $($rest:tt)*) => {
+        make_app_setup!(@options $setup [$update] $devtools $persist $($rest)*);
+    };
+    (@options $setup:tt [$update:ident] [] $persist:tt, devtools = $devtools:expr, $($rest:tt)*) => {
+        make_app_setup!(@options $setup [$update] [$update $devtools] $persist, $($rest)*);
+    };
+    (@options $setup:tt [$update:ident] [] $persist:tt, devtools = $devtools:expr) => {
+        make_app_setup!(@options $setup [$update] [$update $devtools] $persist);
+    };
+    (@options $setup:tt $update:tt $devtools:tt [], persist = $persist:expr, $($rest:tt)*) => {
+        make_app_setup!(@options $setup $update $devtools [$persist], $($rest)*);
+    };
+    (@options $setup:tt $update:tt $devtools:tt [], persist = $persist:expr) => {
+        make_app_setup!(@options $setup $update $devtools [$persist]);
+    };
+
+    (@setup [$app_setup_name:ident $app_state_thread_local_name:ident $render:ident] [$($update:ident)*]
+        [$($devtools_update:ident $devtools:expr)*] [$($persist:expr)*]) => {
+        fn _do_not_use_make_app_setup_twice_in_one_file() -> Option<$crate::html::HtmlNode> {
+            use $crate::observable::Observable;
+
+            $(
+                if $devtools {
+                    return $crate::devtools::frame(&$app_state_thread_local_name, $render, $devtools_update);
+                }
+            )*
+
+            $(
+                $app_state_thread_local_name.with(|root| $crate::update::process(&mut *root.borrow_mut(), $update));
+            )*
+            let has_changed = $app_state_thread_local_name.with(|root| root.borrow().changes().has_changed());
+            let components_changed = $crate::component::take_changed();
+            if !has_changed && !components_changed { return None };
+            Some($app_state_thread_local_name.with(|state| {
+                // Render first, so the state can tell what changed since the last frame while it is rendered
+                let node: $crate::html::HtmlNode = $render(&*state.borrow()).into();
+                state.borrow_mut().changes_mut().finish_frame();
+                node
+            }))
         }
 
         pub fn $app_setup_name() {
-            use html::RENDER;
+            use $crate::html::RENDER;
+            $(
+                $persist.start(&$app_state_thread_local_name);
+            )*
             RENDER.with(|root| {
                 assert!(root.get().is_none());
                 root.set(Some(_do_not_use_make_app_setup_twice_in_one_file))
             });
         }
     }
-}
\ No newline at end of file
+}
+
+/// Anything that can be placed inside an element by `html!`: text, elements, or collections of them
+pub trait Child {
+    fn append_to(self, parent: HtmlElement) -> HtmlElement;
+}
+
+impl Child for HtmlElement {
+    fn append_to(self, parent: HtmlElement) -> HtmlElement { parent.child(self) }
+}
+
+impl Child for HtmlNode {
+    fn append_to(self, parent: HtmlElement) -> HtmlElement { parent.child(self) }
+}
+
+impl<T: Child> Child for Option<T> {
+    fn append_to(self, parent: HtmlElement) -> HtmlElement {
+        match self {
+            Some(child) => child.append_to(parent),
+            None => parent,
+        }
+    }
+}
+
+impl<T: Child> Child for Vec<T> {
+    fn append_to(self, parent: HtmlElement) -> HtmlElement {
+        self.into_iter().fold(parent, |parent, child| child.append_to(parent))
+    }
+}
+
+macro_rules! text_child {
+    ($($t:ty),*) => {
+        $(
+            impl<'a> Child for $t {
+                fn append_to(self, parent: HtmlElement) -> HtmlElement { parent.text(self) }
+            }
+        )*
+    }
+}
+
+text_child!(String, &'a str, &'a String, char, bool, i32, i64, u32, u64, usize, f32, f64);
+
+/// Builds an `HtmlElement` from markup:
+///
+/// ```ignore
+/// html!{ <div class="row" data-id={item.id}>
+///     <p>{&item.name}</p>
+///     {for item.tags.iter().map(render_tag)}
+///     <button onclick={handler}>"Edit"</button>
+/// </div> }
+/// ```
+///
+/// Tags and attributes turn into calls to the functions in `tags` and the matching builder methods,
+/// so a misspelled name is reported where it is written. Text must be quoted, `{expr}` accepts
+/// anything implementing `Child` and `{for iter}` adds every element an iterator yields.
+/// Every tag and attribute is one step of recursion, so large templates need `#![recursion_limit]` raised.
+#[macro_export]
+macro_rules! html {
+    // Closing tags finish the element on top of the stack and add it to the one below it
+    (@parse [ { $tag:ident $($top:tt)* } { $ptag:ident $($parent:tt)* } $($stack:tt)* ] < / $close:ident > $($rest:tt)*) => {
+        html!(@parse [ { $ptag $($parent)* .child(html!(@close $tag $close $($top)*)) } $($stack)* ] $($rest)*)
+    };
+    (@parse [ { $tag:ident $($top:tt)* } ] < / $close:ident >) => {
+        html!(@close $tag $close $($top)*)
+    };
+    (@parse [ $($stack:tt)* ] < $tag:ident $($rest:tt)*) => {
+        html!(@attrs [ $($stack)* ] ($crate::tags::$tag()) $tag $($rest)*)
+    };
+    (@parse [ { $tag:ident $($top:tt)* } $($stack:tt)* ] { for $($iter:tt)* } $($rest:tt)*) => {
+        html!(@parse [ { $tag $($top)* .children($($iter)*) } $($stack)* ] $($rest)*)
+    };
+    (@parse [ { $tag:ident $($top:tt)* } $($stack:tt)* ] $child:tt $($rest:tt)*) => {
+        html!(@parse [ { $tag $($top)* .append($child) } $($stack)* ] $($rest)*)
+    };
+
+    (@attrs [ $($stack:tt)* ] ($($elem:tt)*) $tag:ident > $($rest:tt)*) => {
+        html!(@parse [ { $tag $($elem)* } $($stack)* ] $($rest)*)
+    };
+    (@attrs [ { $ptag:ident $($parent:tt)* } $($stack:tt)* ] ($($elem:tt)*) $tag:ident / > $($rest:tt)*) => {
+        html!(@parse [ { $ptag $($parent)* .child($($elem)*) } $($stack)* ] $($rest)*)
+    };
+    (@attrs [] ($($elem:tt)*) $tag:ident / >) => {
+        $($elem)*
+    };
+    // `type` and `for` are keywords, so they can't be builder methods
+    (@attrs $stack:tt ($($elem:tt)*) $tag:ident type = $value:tt $($rest:tt)*) => {
+        html!(@attrs $stack ($($elem)* .attr("type", $value)) $tag $($rest)*)
+    };
+    (@attrs $stack:tt ($($elem:tt)*) $tag:ident for = $value:tt $($rest:tt)*) => {
+        html!(@attrs $stack ($($elem)* .attr("for", $value)) $tag $($rest)*)
+    };
+    (@attrs $stack:tt ($($elem:tt)*) $tag:ident $name:ident = $value:tt $($rest:tt)*) => {
+        html!(@attrs $stack ($($elem)* .$name($value)) $tag $($rest)*)
+    };
+    // Hyphenated names like data-* and aria-* are passed through as plain attributes
+    (@attrs $stack:tt ($($elem:tt)*) $tag:ident $first:ident $(- $part:ident)+ = $value:tt $($rest:tt)*) => {
+        html!(@attrs $stack ($($elem)* .attr(concat!(stringify!($first) $(, "-", stringify!($part))+), $value)) $tag $($rest)*)
+    };
+
+    // Fails to type check, pointing at the closing tag, unless it names the tag that was opened
+    (@close $open:ident $close:ident $($elem:tt)*) => {{
+        #[allow(dead_code, non_camel_case_types)]
+        fn closing_tag_must_match_opening_tag() { struct $open; let _: $close = $open; }
+        $($elem)*
+    }};
+
+    (< $($tokens:tt)*) => {
+        html!(@parse [] < $($tokens)*)
+    };
+}
@@ -0,0 +1,39 @@
+extern crate futures;
+#[cfg(test)]
+#[macro_use]
+extern crate ion_derive;
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "persist")]
+extern crate serde;
+#[cfg(all(test, feature = "persist"))]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "persist")]
+#[macro_use]
+extern crate serde_json;
+
+#[macro_use]
+pub mod observable;
+#[macro_use]
+pub mod html;
+pub mod diff;
+pub mod component;
+pub mod memo;
+pub mod update;
+pub mod history;
+pub mod devtools;
+pub mod task;
+pub mod executor;
+pub mod timer;
+#[cfg(feature = "persist")]
+pub mod persist;
+mod serialize;
+pub mod tags;
+pub mod backend;
+
+/// The code `#[derive(Observable)]` generates refers to ion as `::ion`, which this makes work inside
+/// ion too
+mod ion {
+    pub use observable;
+}
@@ -0,0 +1,191 @@
+//! Skips rendering parts of the tree whose inputs did not change:
+//!
+//! ```ignore
+//! memo("items", state.get_items().clone(), || html!{ <ul>{for state.get_items().iter().map(render_item)}</ul> })
+//! ```
+//!
+//! While the dependencies compare equal, the element from the last render is reused as is, and the
+//! diff skips over it without looking inside.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use component;
+use html;
+use html::HtmlElement;
+use html::HtmlNode;
+
+thread_local!(static MEMOS: RefCell<HashMap<String, Memo>> = RefCell::new(HashMap::new()));
+
+struct Memo {
+    deps: Box<Any>,
+    tree: Rc<HtmlElement>,
+    /// The ids of the components rendered inside, which have to be kept alive while the tree is reused
+    components: Vec<String>,
+    used: bool,
+}
+
+/// Renders the element, unless the memo with the same key was rendered last frame with equal `deps`.
+/// Every memo needs its own key, and can only be used once a frame, since its tree keeps its ids
+/// wherever it ends up. Components inside are rendered again when their state changes.
+pub fn memo<K: ToString, D: PartialEq + 'static, F: FnOnce() -> HtmlElement>(key: K, deps: D, render: F) -> HtmlNode {
+    let key = key.to_string();
+    let cached = MEMOS.with(|memos| {
+        if let Some(memo) = memos.borrow_mut().get_mut(&key) {
+            if memo.used {
+                panic!("The memo {:?} was used twice in one frame; every memo needs its own key", key);
+            }
+            if memo.deps.downcast_ref::<D>() == Some(&deps) && !component::any_changed(&memo.components) {
+                memo.used = true;
+                component::keep(&memo.components);
+                return Some(memo.tree.clone());
+            }
+        }
+        None
+    });
+    if let Some(tree) = cached {
+        return HtmlNode::Memo(tree);
+    }
+
+    let first_component = component::rendered_count();
+    let mut elem = render();
+    // The ids can't depend on where the memo is, since the tree is reused wherever it ends up
+    if elem.key.is_none() {
+        elem.id = format!("{}-memo", html::key_id(&key));
+    }
+    elem.expand_children();
+    let tree = Rc::new(elem);
+
+    let components = component::rendered_since(first_component);
+    MEMOS.with(|memos| memos.borrow_mut().insert(key, Memo { deps: Box::new(deps), tree: tree.clone(), components, used: true }));
+    HtmlNode::Memo(tree)
+}
+
+/// Forgets the memos that were not rendered this frame
+pub(crate) fn finish_frame() {
+    MEMOS.with(|memos| {
+        let mut memos = memos.borrow_mut();
+        memos.retain(|_, memo| memo.used);
+        for memo in memos.values_mut() {
+            memo.used = false;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+
+    use backend::headless::Harness;
+    use component;
+    use component::Component;
+    use component::Scope;
+    use diff;
+    use html::HtmlElement;
+    use html::HtmlNode;
+    use html::RENDER;
+    use tags::*;
+    use super::memo;
+
+    thread_local!(static DEPS: Cell<u32> = Cell::new(0));
+    thread_local!(static RENDERS: Cell<u32> = Cell::new(0));
+    thread_local!(static HOOKS: RefCell<Vec<&'static str>> = RefCell::new(vec![]));
+
+    struct Counter { count: u32 }
+
+    impl Component for Counter {
+        type Props = ();
+        fn create(_: &()) -> Counter { Counter { count: 0 } }
+        fn render(&self, _: &(), scope: &Scope<Counter>) -> HtmlNode {
+            let scope = scope.clone();
+            button().text(format!("Clicks: {}", self.count))
+                .on("click", move |_| scope.update(|counter| counter.count += 1))
+                .into()
+        }
+        fn mounted(&mut self) { HOOKS.with(|hooks| hooks.borrow_mut().push("mounted")) }
+        fn updated(&mut self) { HOOKS.with(|hooks| hooks.borrow_mut().push("updated")) }
+        fn unmounted(&mut self) { HOOKS.with(|hooks| hooks.borrow_mut().push("unmounted")) }
+    }
+
+    fn list() -> HtmlElement {
+        RENDERS.with(|renders| renders.set(renders.get() + 1));
+        let deps = DEPS.with(|deps| deps.get());
+        ul().child(li().text(format!("Deps: {}", deps))).child(component::component::<Counter>(()))
+    }
+
+    fn view() -> Option<HtmlNode> {
+        Some(div().child(memo("list", DEPS.with(|deps| deps.get()), list)).into())
+    }
+
+    fn setup() {
+        RENDER.with(|root| root.set(Some(view)));
+    }
+
+    fn renders() -> u32 {
+        RENDERS.with(|renders| renders.get())
+    }
+
+    fn hooks() -> Vec<&'static str> {
+        HOOKS.with(|hooks| hooks.borrow().clone())
+    }
+
+    #[test]
+    fn reused_memo_has_nothing_to_patch() {
+        let first = memo("list", 1, list);
+        super::finish_frame();
+        component::finish_frame();
+        let second = memo("list", 1, list);
+        assert_eq!(renders(), 1);
+        assert!(diff::diff(&[first], &[second]).is_empty());
+    }
+
+    #[test]
+    fn renders_again_when_the_deps_change() {
+        let mut app = Harness::new(setup);
+        app.frame();
+        assert_eq!(renders(), 1);
+
+        DEPS.with(|deps| deps.set(1));
+        app.frame();
+        assert_eq!(renders(), 2);
+        assert!(app.find_by_text("Deps: 1").is_some());
+    }
+
+    #[test]
+    fn keeps_the_components_inside_and_renders_them_when_they_change() {
+        let mut app = Harness::new(setup);
+        app.frame();
+        assert_eq!(hooks(), vec!["mounted"]);
+
+        let button = app.find_by_text("Clicks: 0").unwrap();
+        app.fire(&button, "click");
+        app.frame();
+        assert_eq!(renders(), 2);
+        assert!(app.find_by_text("Clicks: 1").is_some());
+
+        // Reusing the memo keeps the instance, without calling its hooks
+        app.frame();
+        app.frame();
+        assert_eq!(renders(), 2);
+        assert_eq!(hooks(), vec!["mounted", "updated"]);
+
+        DEPS.with(|deps| deps.set(1));
+        app.frame();
+        assert!(app.find_by_text("Clicks: 1").is_some());
+        assert_eq!(hooks(), vec!["mounted", "updated", "updated"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "was used twice in one frame")]
+    fn rejects_a_key_used_twice_in_a_frame() {
+        fn twice() -> Option<HtmlNode> {
+            Some(div().child(memo("list", 0, list)).child(memo("list", 0, list)).into())
+        }
+        RENDER.with(|root| root.set(Some(twice)));
+        let mut app = Harness::new(|| {});
+        app.frame();
+    }
+}
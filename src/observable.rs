@@ -1,38 +1,614 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::rc::Rc;
+use std::thread;
+
+#[cfg(feature = "persist")]
+use serde::Deserialize;
+#[cfg(feature = "persist")]
+use serde::Deserializer;
+#[cfg(feature = "persist")]
+use serde::Serialize;
+#[cfg(feature = "persist")]
+use serde::Serializer;
+
+/// App state that keeps track of which of its fields changed. Implemented by `#[derive(Observable)]`.
+pub trait Observable: Sized {
+    fn changes(&self) -> &Changes<Self>;
+    fn changes_mut(&mut self) -> &mut Changes<Self>;
+
+    /// Makes several changes as one: subscribers hear about them, and the next frame sees them, once
+    /// `f` returns
+    ///
+    /// ```ignore
+    /// state.batch(|state| {
+    ///     state.get_items_mut().push(item);
+    ///     state.set_new_item_name(String::new());
+    /// });
+    /// ```
+    fn batch<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R {
+        Changes::batch(self, f)
+    }
+
+    /// Like `batch`, but if `f` returns an error the state is put back the way it was, and nothing
+    /// counts as changed
+    fn try_batch<T, E, F: FnOnce(&mut Self) -> Result<T, E>>(&mut self, f: F) -> Result<T, E> where Self: Clone {
+        Changes::try_batch(self, f)
+    }
+}
+
+/// Counts changes to each field of `S`, and holds the callbacks that want to hear about them
+pub struct Changes<S> {
+    revision: u64,
+    /// The revision each field last changed at
+    fields: HashMap<&'static str, u64>,
+    /// The revision when the last frame was rendered
+    rendered: u64,
+    subscribers: HashMap<&'static str, Vec<Rc<Fn(&S)>>>,
+    /// Called when any field changes
+    subscribers_to_all: Vec<Rc<Fn(&S)>>,
+    /// The values of computed fields, with the revisions of the fields they were computed from
+    computed: RefCell<HashMap<&'static str, (Vec<u64>, Box<Any>)>>,
+    /// How many batches are in progress
+    batches: usize,
+    /// The fields changed by the batches in progress, whose subscribers are called once they finish
+    pending: Vec<&'static str>,
+}
+
+impl<S: Observable> Changes<S> {
+    /// Starts out changed, so the first frame is rendered
+    pub fn new() -> Changes<S> {
+        Changes { revision: 1, fields: HashMap::new(), rendered: 0, subscribers: HashMap::new(), subscribers_to_all: vec![],
+                  computed: RefCell::new(HashMap::new()), batches: 0, pending: vec![] }
+    }
+
+    /// Goes up every time the field changes, so it can be passed to `memo` as a dependency
+    pub fn revision(&self, field: &str) -> u64 {
+        self.fields.get(field).cloned().unwrap_or(0)
+    }
+
+    /// Whether anything changed since the last frame was rendered. Changes made by a batch only count
+    /// once it finishes.
+    pub fn has_changed(&self) -> bool {
+        self.batches == 0 && self.revision > self.rendered
+    }
+
+    pub fn field_changed(&self, field: &str) -> bool {
+        self.revision(field) > self.rendered
+    }
+
+    /// The fields that changed since the last frame was rendered
+    pub fn changed_fields(&self) -> Vec<&'static str> {
+        self.fields.iter().filter(|&(_, &revision)| revision > self.rendered).map(|(&field, _)| field).collect()
+    }
+
+    /// The fields that changed since `earlier` was cloned from these changes, sorted by name
+    pub fn changed_since(&self, earlier: &Changes<S>) -> Vec<&'static str> {
+        let mut changed: Vec<&'static str> = self.fields.keys().chain(earlier.fields.keys())
+            .filter(|field| self.revision(field) != earlier.revision(field))
+            .cloned().collect();
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+
+    /// Called by `make_app_setup!` once the state is rendered
+    pub fn finish_frame(&mut self) {
+        self.rendered = self.revision;
+    }
+
+    pub fn subscribe<F: Fn(&S) + 'static>(&mut self, field: &'static str, callback: F) {
+        self.subscribers.entry(field).or_insert_with(Vec::new).push(Rc::new(callback));
+    }
+
+    /// The value `compute` returns, which is kept until one of the `fields` it is computed from changes.
+    /// Used by the computed fields of `observable!`.
+    pub fn computed<T: 'static, F: FnOnce() -> T>(&self, name: &'static str, fields: &[&str], compute: F) -> Rc<T> {
+        let revisions: Vec<u64> = fields.iter().map(|field| self.revision(field)).collect();
+        if let Some(&(ref computed_at, ref value)) = self.computed.borrow().get(name) {
+            if *computed_at == revisions {
+                if let Some(value) = value.downcast_ref::<Rc<T>>() {
+                    return value.clone();
+                }
+            }
+        }
+        // Not borrowed while computing, so computed fields can use each other
+        let value = Rc::new(compute());
+        self.computed.borrow_mut().insert(name, (revisions, Box::new(value.clone())));
+        value
+    }
+
+    /// Calls `callback` with the new state every time any field changes
+    pub fn subscribe_all<F: Fn(&S) + 'static>(&mut self, callback: F) {
+        self.subscribers_to_all.push(Rc::new(callback));
+    }
+
+    /// Records a change to the field, and calls its subscribers with the new state, or leaves that to
+    /// the batch in progress
+    pub fn changed(state: &mut S, field: &'static str) {
+        let subscribers = {
+            let changes = state.changes_mut();
+            changes.revision += 1;
+            changes.fields.insert(field, changes.revision);
+            if changes.batches > 0 {
+                changes.pending.push(field);
+                return;
+            }
+            changes.subscribers_of(&[field])
+        };
+        for subscriber in subscribers {
+            subscriber(state);
+        }
+    }
+
+    /// The subscribers to any of the fields, each once, followed by the subscribers to all of them
+    fn subscribers_of(&self, fields: &[&'static str]) -> Vec<Rc<Fn(&S)>> {
+        let mut subscribers: Vec<Rc<Fn(&S)>> = vec![];
+        for field in fields {
+            for subscriber in self.subscribers.get(field).into_iter().flat_map(|subscribers| subscribers.iter()) {
+                if !subscribers.iter().any(|added| Rc::ptr_eq(added, subscriber)) {
+                    subscribers.push(subscriber.clone());
+                }
+            }
+        }
+        subscribers.extend(self.subscribers_to_all.iter().cloned());
+        subscribers
+    }
+
+    /// Used by `Observable::batch`. Batches can be nested, and the outermost one calls the subscribers.
+    pub fn batch<R, F: FnOnce(&mut S) -> R>(state: &mut S, f: F) -> R {
+        state.changes_mut().batches += 1;
+        let batch = Batch { state };
+        f(&mut *batch.state)
+    }
+
+    /// Used by `Observable::try_batch`
+    pub fn try_batch<T, E, F: FnOnce(&mut S) -> Result<T, E>>(state: &mut S, f: F) -> Result<T, E> where S: Clone {
+        let snapshot = state.clone();
+        let pending = state.changes().pending.len();
+        let rendered = state.changes().revision == state.changes().rendered;
+        state.changes_mut().batches += 1;
+        let batch = Batch { state };
+        let state = &mut *batch.state;
+        let result = f(state);
+        if result.is_err() {
+            // The revision keeps going up, so nothing computed during the batch is mistaken for current
+            let mut changes = mem::replace(state.changes_mut(), Changes::new());
+            changes.fields = snapshot.changes().fields.clone();
+            changes.pending.truncate(pending);
+            if rendered {
+                changes.rendered = changes.revision;
+            }
+            *state = snapshot;
+            *state.changes_mut() = changes;
+        }
+        result
+    }
+
+    fn finish_batch(state: &mut S) {
+        let subscribers = {
+            let changes = state.changes_mut();
+            changes.batches -= 1;
+            if changes.batches > 0 || changes.pending.is_empty() {
+                return;
+            }
+            let fields = mem::replace(&mut changes.pending, vec![]);
+            changes.subscribers_of(&fields)
+        };
+        for subscriber in subscribers {
+            subscriber(state);
+        }
+    }
+
+    /// Puts back the fields of an earlier clone of the state, and records a change to each field that
+    /// changed since the clone was made
+    pub fn restore(state: &mut S, snapshot: &S) where S: Clone {
+        let changed = state.changes().changed_since(snapshot.changes());
+        let changes = state.changes().clone();
+        *state = snapshot.clone();
+        *state.changes_mut() = changes;
+        for field in changed {
+            Changes::changed(state, field);
+        }
+    }
+}
+
+/// Finishes a batch once it goes out of scope, even if the batch panicked. Otherwise the state would
+/// look like it is still in a batch, and never be rendered again.
+struct Batch<'a, S: Observable + 'a> {
+    state: &'a mut S,
+}
+
+impl<'a, S: Observable + 'a> Drop for Batch<'a, S> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // Subscribers are not called while unwinding. Their fields are still pending, so the next
+            // batch that finishes calls them.
+            self.state.changes_mut().batches -= 1;
+        } else {
+            Changes::finish_batch(self.state);
+        }
+    }
+}
+
+impl<S: Observable> Default for Changes<S> {
+    fn default() -> Changes<S> { Changes::new() }
+}
+
+/// Clones share the subscribers, and compute their computed fields again. They are not part of the
+/// batches in progress.
+impl<S> Clone for Changes<S> {
+    fn clone(&self) -> Changes<S> {
+        Changes {
+            revision: self.revision,
+            fields: self.fields.clone(),
+            rendered: self.rendered,
+            subscribers: self.subscribers.clone(),
+            subscribers_to_all: self.subscribers_to_all.clone(),
+            computed: RefCell::new(HashMap::new()),
+            batches: 0,
+            pending: vec![],
+        }
+    }
+}
+
+/// The bookkeeping is left out, so printing a state only shows its fields
+impl<S> fmt::Debug for Changes<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Changes")
+    }
+}
+
+/// Bookkeeping never makes two states different, so comparing states only compares their fields
+impl<S> PartialEq for Changes<S> {
+    fn eq(&self, _: &Changes<S>) -> bool { true }
+}
+
+/// Only the fields of a state are saved, so this is written as nothing, and read back as no changes
+#[cfg(feature = "persist")]
+impl<S> Serialize for Changes<S> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.serialize_unit()
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<'de, S: Observable> Deserialize<'de> for Changes<S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Changes<S>, D::Error> {
+        <()>::deserialize(deserializer)?;
+        Ok(Changes::new())
+    }
+}
+
+/// Mutable access to one field, which only counts as a change if the field is actually written through it
+pub struct FieldMut<'a, S: Observable + 'a, T: 'a> {
+    state: &'a mut S,
+    field: &'static str,
+    get: fn(&S) -> &T,
+    get_mut: fn(&mut S) -> &mut T,
+    written: bool,
+}
+
+impl<'a, S: Observable + 'a, T: 'a> FieldMut<'a, S, T> {
+    pub fn new(state: &'a mut S, field: &'static str, get: fn(&S) -> &T, get_mut: fn(&mut S) -> &mut T) -> FieldMut<'a, S, T> {
+        FieldMut { state, field, get, get_mut, written: false }
+    }
+}
+
+impl<'a, S: Observable + 'a, T: 'a> Deref for FieldMut<'a, S, T> {
+    type Target = T;
+    fn deref(&self) -> &T { (self.get)(&*self.state) }
+}
+
+impl<'a, S: Observable + 'a, T: 'a> DerefMut for FieldMut<'a, S, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.written = true;
+        (self.get_mut)(&mut *self.state)
+    }
+}
+
+/// Subscribers are called once the field is no longer borrowed
+impl<'a, S: Observable + 'a, T: 'a> Drop for FieldMut<'a, S, T> {
+    fn drop(&mut self) {
+        if self.written {
+            Changes::changed(&mut *self.state, self.field);
+        }
+    }
+}
+
+/// Declares app state, with `#[derive(Observable)]` and a `new()` that fills in the defaults. Fields
+/// without a default become arguments to `new()`, in order. Bounds on the type parameters go in
+/// the `where` clause. Needs `#[macro_use] extern crate ion_derive;`.
+///
+/// Computed fields become methods that return the value of their expression, which is only evaluated
+/// again once one of the fields listed in parentheses changed.
+///
+/// ```ignore
+/// observable! {
+///     /// Shared with the other modules
+///     #[derive(Clone, Debug)]
+///     pub struct List<T> where T: Clone {
+///         pub items: Vec<T> = vec![],
+///         title: String,
+///         pub computed heading(title, items): String = format!("{} ({})", title, items.len()),
+///     }
+/// }
+///
+/// let list = List::<u32>::new("Groceries".to_string());
+/// assert_eq!(*list.heading(), "Groceries (0)");
+/// ```
 #[macro_export]
 macro_rules! observable {
-    (struct $name:ident {
-        $($field:ident : $t:ty = $e:expr $(,)*)*
-    }) => {
-        struct $name {
+    // The struct's attributes and visibility
+    ($(#[$attr:meta])* pub ($($restriction:tt)*) struct $name:ident $($rest:tt)*) => {
+        observable!(@generics [$(#[$attr])*] [pub ($($restriction)*)] $name $($rest)*);
+    };
+    ($(#[$attr:meta])* pub struct $name:ident $($rest:tt)*) => {
+        observable!(@generics [$(#[$attr])*] [pub] $name $($rest)*);
+    };
+    ($(#[$attr:meta])* struct $name:ident $($rest:tt)*) => {
+        observable!(@generics [$(#[$attr])*] [] $name $($rest)*);
+    };
+
+    (@generics $attrs:tt $vis:tt $name:ident < $($param:tt),* > $($rest:tt)*) => {
+        observable!(@where $attrs $vis $name [$($param),*] [] $($rest)*);
+    };
+    (@generics $attrs:tt $vis:tt $name:ident $($rest:tt)*) => {
+        observable!(@where $attrs $vis $name [] [] $($rest)*);
+    };
+
+    // Everything up to the body is the where clause
+    (@where $attrs:tt $vis:tt $name:ident $params:tt $where_clause:tt { $($fields:tt)* }) => {
+        observable!(@fields [$attrs $vis $name $params $where_clause] [] [] [] [] $($fields)*);
+    };
+    (@where $attrs:tt $vis:tt $name:ident $params:tt [$($where_clause:tt)*] $next:tt $($rest:tt)*) => {
+        observable!(@where $attrs $vis $name $params [$($where_clause)* $next] $($rest)*);
+    };
+
+    // Each field's attributes and visibility. The accumulators are every field, the fields with a
+    // default, the fields that are passed to new(), and the computed fields.
+    (@fields $header:tt $fields:tt $defaults:tt $args:tt $computed:tt) => {
+        observable!(@emit $header $fields $defaults $args $computed);
+    };
+    (@fields $header:tt $fields:tt $defaults:tt $args:tt $computed:tt $(#[$field_attr:meta])* pub ($($restriction:tt)*) $($rest:tt)*) => {
+        observable!(@field $header $fields $defaults $args $computed [$(#[$field_attr])*] [pub ($($restriction)*)] $($rest)*);
+    };
+    (@fields $header:tt $fields:tt $defaults:tt $args:tt $computed:tt $(#[$field_attr:meta])* pub $($rest:tt)*) => {
+        observable!(@field $header $fields $defaults $args $computed [$(#[$field_attr])*] [pub] $($rest)*);
+    };
+    (@fields $header:tt $fields:tt $defaults:tt $args:tt $computed:tt $(#[$field_attr:meta])* $field:ident $($rest:tt)*) => {
+        observable!(@field $header $fields $defaults $args $computed [$(#[$field_attr])*] [] $field $($rest)*);
+    };
+
+    // `computed name(fields it is computed from): Type = expression using those fields`
+    (@field $header:tt $fields:tt $defaults:tt $args:tt [$($computed:tt)*] $field_attrs:tt $field_vis:tt
+        computed $field:ident ($($dependency:ident),*) : $t:ty = $e:expr, $($rest:tt)*) => {
+        observable!(@fields $header $fields $defaults $args [$($computed)* [$field_attrs $field_vis $field [$($dependency),*] [$t] [$e]]] $($rest)*);
+    };
+    (@field $header:tt $fields:tt $defaults:tt $args:tt $computed:tt $field_attrs:tt $field_vis:tt
+        computed $field:ident ($($dependency:ident),*) : $t:ty = $e:expr) => {
+        observable!(@field $header $fields $defaults $args $computed $field_attrs $field_vis computed $field ($($dependency),*) : $t = $e,);
+    };
+    (@field $header:tt [$($fields:tt)*] [$($defaults:tt)*] $args:tt $computed:tt $field_attrs:tt $field_vis:tt
+        $field:ident : $t:ty = $e:expr, $($rest:tt)*) => {
+        observable!(@fields $header [$($fields)* [$field_attrs $field_vis $field $t]] [$($defaults)* [$field $e]] $args $computed $($rest)*);
+    };
+    (@field $header:tt $fields:tt $defaults:tt $args:tt $computed:tt $field_attrs:tt $field_vis:tt $field:ident : $t:ty = $e:expr) => {
+        observable!(@field $header $fields $defaults $args $computed $field_attrs $field_vis $field : $t = $e,);
+    };
+    (@field $header:tt [$($fields:tt)*] $defaults:tt [$($args:tt)*] $computed:tt $field_attrs:tt $field_vis:tt
+        $field:ident : $t:ty, $($rest:tt)*) => {
+        observable!(@fields $header [$($fields)* [$field_attrs $field_vis $field $t]] $defaults [$($args)* [$field $t]] $computed $($rest)*);
+    };
+    (@field $header:tt $fields:tt $defaults:tt $args:tt $computed:tt $field_attrs:tt $field_vis:tt $field:ident : $t:ty) => {
+        observable!(@field $header $fields $defaults $args $computed $field_attrs $field_vis $field : $t,);
+    };
+
+    (@emit [[$($attr:tt)*] [$($vis:tt)*] $name:ident [$($param:tt),*] [$($where_clause:tt)*]]
+        [$([[$($field_attr:tt)*] [$($field_vis:tt)*] $field:ident $t:ty])*]
+        [$([$default:ident $e:expr])*]
+        [$([$arg:ident $arg_t:ty])*]
+        [$([[$($computed_attr:tt)*] [$($computed_vis:tt)*] $computed:ident [$($dependency:ident),*] [$computed_t:ty] [$computed_e:expr]])*]) => {
+        $($attr)*
+        #[derive(Observable)]
+        $($vis)* struct $name<$($param),*> $($where_clause)* {
             $(
-                $field : $t,
+                $($field_attr)*
+                $($field_vis)* $field : $t,
             )*
-            has_changed: bool,
+            changes: $crate::observable::Changes<$name<$($param),*>>,
         }
 
-        impl $name {
-            pub fn new() -> $name {
+        impl<$($param),*> $name<$($param),*> $($where_clause)* {
+            #[allow(dead_code)]
+            pub fn new($($arg : $arg_t),*) -> $name<$($param),*> {
                 $name {
                     $(
-                        $field : $e,
+                        $default : $e,
                     )*
-                    has_changed: true,
-                 }
+                    $(
+                        $arg,
+                    )*
+                    changes: $crate::observable::Changes::new(),
+                }
             }
 
             $(
-                interpolate_idents! {
-                    #[allow(dead_code)]
-                    fn [get_ $field](&self) -> &$t { &self.$field }
-                    #[allow(dead_code)]
-                    fn [get_ $field _mut](&mut self) -> &mut $t { self.has_changed = true; &mut self.$field }
-                    #[allow(dead_code)]
-                    fn [set_ $field](&mut self, val: $t) {
-                        self.$field = val;
-                        self.has_changed = true;
-                    }
+                $($computed_attr)*
+                #[allow(dead_code)]
+                $($computed_vis)* fn $computed(&self) -> ::std::rc::Rc<$computed_t> {
+                    self.changes.computed(stringify!($computed), &[$(stringify!($dependency)),*], || {
+                        $(
+                            let $dependency = &self.$dependency;
+                        )*
+                        $computed_e
+                    })
                 }
             )*
         }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::panic;
+    use std::rc::Rc;
+
+    use observable::Changes;
+    use observable::Observable;
+
+    observable! {
+        #[derive(Clone, Debug)]
+        struct State {
+            a: u32 = 0,
+            b: u32 = 0,
+            computed sum(a, b): u32 = a + b,
+        }
+    }
+
+    /// A rendered state, and the fields its subscribers heard about
+    fn state() -> (State, Rc<RefCell<Vec<&'static str>>>) {
+        let mut state = State::new();
+        let heard = Rc::new(RefCell::new(vec![]));
+        {
+            let heard = heard.clone();
+            state.subscribe_a(move |_| heard.borrow_mut().push("a"));
+        }
+        {
+            let heard = heard.clone();
+            state.subscribe_b(move |_| heard.borrow_mut().push("b"));
+        }
+        state.changes_mut().finish_frame();
+        (state, heard)
+    }
+
+    #[test]
+    fn batch_calls_subscribers_once_it_finishes() {
+        let (mut state, heard) = state();
+        state.batch(|state| {
+            state.set_a(1);
+            state.set_a(2);
+            state.batch(|state| state.set_b(1));
+            assert!(heard.borrow().is_empty());
+            assert!(!state.changes().has_changed());
+        });
+        assert_eq!(*heard.borrow(), vec!["a", "b"]);
+        assert!(state.changes().has_changed());
+    }
+
+    #[test]
+    fn batch_that_panicked_is_finished() {
+        let (mut state, heard) = state();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            state.batch(|state| {
+                state.set_a(1);
+                panic!("in the middle of a batch");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(state.changes().has_changed());
+        assert!(heard.borrow().is_empty());
+
+        // The next batch calls the subscribers of what the panicked one changed
+        state.batch(|state| state.set_b(1));
+        assert_eq!(*heard.borrow(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn try_batch_that_panicked_is_finished() {
+        let (mut state, _) = state();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            state.try_batch(|state| -> Result<(), ()> {
+                state.set_a(1);
+                panic!("in the middle of a batch");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(state.changes().has_changed());
+    }
+
+    #[test]
+    fn try_batch_keeps_changes_if_it_succeeds() {
+        let (mut state, heard) = state();
+        let result: Result<u32, ()> = state.try_batch(|state| {
+            state.set_a(1);
+            Ok(*state.get_a())
+        });
+        assert_eq!(result, Ok(1));
+        assert_eq!(*state.get_a(), 1);
+        assert_eq!(*heard.borrow(), vec!["a"]);
+        assert_eq!(state.changes().changed_fields(), vec!["a"]);
+    }
+
+    #[test]
+    fn try_batch_that_failed_puts_everything_back() {
+        let (mut state, heard) = state();
+        let revision = state.changes().revision("a");
+        assert_eq!(*state.sum(), 0);
+
+        let result: Result<(), &str> = state.try_batch(|state| {
+            state.set_a(1);
+            state.set_b(2);
+            // Computed from the changes that are about to be undone
+            assert_eq!(*state.sum(), 3);
+            Err("nope")
+        });
+        assert_eq!(result, Err("nope"));
+        assert_eq!((*state.get_a(), *state.get_b()), (0, 0));
+        assert_eq!(state.changes().revision("a"), revision);
+        assert_eq!(*state.sum(), 0);
+        assert!(heard.borrow().is_empty());
+        // Nothing changed since the last frame, so there is nothing to render
+        assert!(!state.changes().has_changed());
+    }
+
+    #[test]
+    fn try_batch_that_failed_keeps_earlier_changes() {
+        let (mut state, heard) = state();
+        state.set_b(1);
+        heard.borrow_mut().clear();
+
+        let _: Result<(), ()> = state.try_batch(|state| {
+            state.set_a(1);
+            Err(())
+        });
+        assert_eq!(*state.get_b(), 1);
+        // The frame for the change before the batch still has to be rendered
+        assert!(state.changes().has_changed());
+        assert_eq!(state.changes().changed_fields(), vec!["b"]);
+        assert!(heard.borrow().is_empty());
+    }
+
+    #[test]
+    fn try_batch_that_failed_inside_a_batch_only_drops_its_own_changes() {
+        let (mut state, heard) = state();
+        state.batch(|state| {
+            state.set_b(1);
+            let _: Result<(), ()> = state.try_batch(|state| {
+                state.set_a(1);
+                Err(())
+            });
+        });
+        assert_eq!((*state.get_a(), *state.get_b()), (0, 1));
+        assert_eq!(*heard.borrow(), vec!["b"]);
+        assert_eq!(state.changes().changed_fields(), vec!["b"]);
+    }
+
+    #[test]
+    fn restore_counts_the_restored_fields_as_changed() {
+        let mut state = State::new();
+        let snapshot = state.clone();
+        state.set_a(1);
+        state.changes_mut().finish_frame();
+
+        Changes::restore(&mut state, &snapshot);
+        assert_eq!(*state.get_a(), 0);
+        assert_eq!(state.changes().changed_fields(), vec!["a"]);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,586 @@
+//! Saves the app state to the user's data directory whenever it changes, and restores it before the
+//! first frame. Pass it to `make_app_setup!`:
+//!
+//! ```ignore
+//! make_app_setup!{ pub fn app_setup() app_thread_state = APP_STATE, render = render,
+//!                  persist = Persist::new("todo", 2).migrate(1, |state| {
+//!                      // Version 2 renamed `name` to `title`
+//!                      let name = state["name"].take();
+//!                      state["title"] = name;
+//!                  }) }
+//! ```
+//!
+//! The state has to implement `Serialize` and `Deserialize`. A change only marks the state as unsaved.
+//! Once it stops changing for a moment, it is serialized on the app's thread and written on a thread
+//! of its own, so typing doesn't serialize or write the state on every keystroke. The embedder calls
+//! `finish` before it exits, so the last changes are written too.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+use std::thread::LocalKey;
+use std::thread::ThreadId;
+use std::time::Duration;
+
+use futures::Future;
+use futures::sync::oneshot;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_json::Value;
+
+use executor;
+use observable::Changes;
+use observable::Observable;
+use timer;
+
+/// How many seconds `finish` waits for an app thread to save, in case it never gets around to it
+const FINISH_TIMEOUT_SECS: u64 = 5;
+
+lazy_static! {
+    /// The threads writing saves, so `finish` can wait for them
+    static ref WRITERS: Mutex<Vec<Writer>> = Mutex::new(vec![]);
+}
+
+thread_local!(static DATA_DIR: RefCell<Option<Option<PathBuf>>> = RefCell::new(None));
+/// Saves what changed right away and stops saving, for each app state saved from this thread
+thread_local!(static FINISHERS: RefCell<Vec<Rc<Fn()>>> = RefCell::new(vec![]));
+
+struct Writer {
+    saves: mpsc::Sender<Save>,
+    /// The thread the state is saved from, which is the only one that can serialize it
+    app_thread: ThreadId,
+    /// Asks the app thread to save what changed right away
+    flush: oneshot::Sender<()>,
+    /// Told once the last save is written
+    done: mpsc::Receiver<()>,
+}
+
+enum Save {
+    Contents(String),
+    /// Stop once everything sent before is written
+    Finish,
+}
+
+/// Keeps track of the changes that were not saved yet, on the app's thread
+struct Saver<S: 'static> {
+    app_state: &'static LocalKey<RefCell<S>>,
+    version: u32,
+    debounce: Duration,
+    writes: mpsc::Sender<Save>,
+    /// Whether the state changed since it was last saved
+    dirty: Cell<bool>,
+    /// Saves once the debounce passed
+    timer: Cell<Option<timer::Handle>>,
+}
+
+impl<S: Serialize + 'static> Saver<S> {
+    /// Saves the state once it stays the same for the debounce
+    fn changed(saver: &Rc<Saver<S>>) {
+        saver.dirty.set(true);
+        if let Some(timer) = saver.timer.take() {
+            timer.cancel();
+        }
+        let later = saver.clone();
+        saver.timer.set(Some(timer::after(saver.debounce, move || later.save())));
+    }
+
+    /// Serializes the state, if it changed, and hands it to the writer
+    fn save(&self) {
+        if let Some(timer) = self.timer.take() {
+            timer.cancel();
+        }
+        if !self.dirty.replace(false) {
+            return;
+        }
+        let version = self.version;
+        let saved = self.app_state.with(|root| json!({ "version": version, "state": &*root.borrow() }));
+        let _ = self.writes.send(Save::Contents(saved.to_string()));
+    }
+
+    fn finish(&self) {
+        self.save();
+        let _ = self.writes.send(Save::Finish);
+    }
+}
+
+pub struct Persist<S> {
+    path: Option<PathBuf>,
+    version: u32,
+    /// Turn data saved by the version they are keyed by into data for the next version
+    migrations: HashMap<u32, Box<Fn(&mut Value)>>,
+    debounce: Duration,
+    state: PhantomData<S>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The file was saved by a later version of the app
+    NewerVersion(u32),
+    /// There is no way to upgrade data saved by this version
+    MissingMigration(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "Could not read the saved state: {}", err),
+            Error::Json(ref err) => write!(f, "The saved state is invalid: {}", err),
+            Error::NewerVersion(version) => write!(f, "The state was saved by a newer version ({})", version),
+            Error::MissingMigration(version) => write!(f, "No migration from version {} of the state", version),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str { "Could not restore the saved state" }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Io(err) }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error { Error::Json(err) }
+}
+
+/// Makes apps started on this thread keep their data in `dir` instead of the usual data directory,
+/// or not save anything if it is `None`. Meant for tests, which shouldn't touch the user's data.
+pub fn set_data_dir(dir: Option<PathBuf>) {
+    DATA_DIR.with(|data_dir| *data_dir.borrow_mut() = Some(dir));
+}
+
+/// Like `set_data_dir`, unless it was already called on this thread. Used by the headless backend.
+pub(crate) fn set_data_dir_unless_set(dir: Option<PathBuf>) {
+    DATA_DIR.with(|data_dir| {
+        let mut data_dir = data_dir.borrow_mut();
+        if data_dir.is_none() {
+            *data_dir = Some(dir);
+        }
+    });
+}
+
+/// The directory apps keep their data in: `$XDG_DATA_HOME` or `~/.local/share` on Linux,
+/// `~/Library/Application Support` on macOS and `%APPDATA%` on Windows, unless `set_data_dir`
+/// changed it
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = DATA_DIR.with(|data_dir| data_dir.borrow().clone()) {
+        return dir;
+    }
+    if cfg!(target_os = "windows") {
+        return env::var_os("APPDATA").map(PathBuf::from);
+    }
+    let home = env::home_dir()?;
+    if cfg!(target_os = "macos") {
+        return Some(home.join("Library").join("Application Support"));
+    }
+    match env::var_os("XDG_DATA_HOME") {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(home.join(".local").join("share")),
+    }
+}
+
+impl<S: Serialize + DeserializeOwned + Observable + Clone + 'static> Persist<S> {
+    /// Keeps the state in `state.json` in a directory named `app_name` in the data directory. `version`
+    /// goes up whenever the saved data changes shape.
+    pub fn new<T: AsRef<str>>(app_name: T, version: u32) -> Persist<S> {
+        Persist {
+            path: data_dir().map(|dir| dir.join(app_name.as_ref()).join("state.json")),
+            version,
+            migrations: HashMap::new(),
+            debounce: Duration::from_millis(500),
+            state: PhantomData,
+        }
+    }
+
+    /// Saves to `path` instead of the data directory
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Persist<S> {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// How long the state has to stay the same before it is written. Half a second by default.
+    pub fn debounce(mut self, delay: Duration) -> Persist<S> {
+        self.debounce = delay;
+        self
+    }
+
+    /// Upgrades data saved by version `from` to version `from + 1`, before it is deserialized
+    pub fn migrate<F: Fn(&mut Value) + 'static>(mut self, from: u32, migration: F) -> Persist<S> {
+        self.migrations.insert(from, Box::new(migration));
+        self
+    }
+
+    /// Reads the saved state, or `None` if nothing was saved yet
+    pub fn restore(&self) -> Result<Option<S>, Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(None),
+        };
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let saved: Value = serde_json::from_reader(io::BufReader::new(file))?;
+        let version = saved["version"].as_u64().unwrap_or(0) as u32;
+        if version > self.version {
+            return Err(Error::NewerVersion(version));
+        }
+        let mut state = saved["state"].clone();
+        for from in version..self.version {
+            match self.migrations.get(&from) {
+                Some(migration) => migration(&mut state),
+                None => return Err(Error::MissingMigration(from)),
+            }
+        }
+        Ok(Some(serde_json::from_value(state)?))
+    }
+
+    /// Restores the saved state into `app_state`, and saves it from then on whenever it changes.
+    /// Used by `make_app_setup!`.
+    pub fn start(self, app_state: &'static LocalKey<RefCell<S>>) {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => {
+                eprintln!("Not saving the app state, since there is no data directory");
+                return;
+            },
+        };
+        match self.restore() {
+            Ok(Some(saved)) => app_state.with(|root| Changes::restore(&mut *root.borrow_mut(), &saved)),
+            Ok(None) => {},
+            Err(err) => {
+                // Moved out of the way instead of being overwritten, so it can still be recovered
+                eprintln!("{}", err);
+                let _ = fs::rename(&path, path.with_extension("json.broken"));
+            },
+        }
+
+        let (writes, done) = spawn_writer(path);
+        let saver = Rc::new(Saver {
+            app_state,
+            version: self.version,
+            debounce: self.debounce,
+            writes: writes.clone(),
+            dirty: Cell::new(false),
+            timer: Cell::new(None),
+        });
+        {
+            let saver = saver.clone();
+            app_state.with(|root| root.borrow_mut().changes_mut().subscribe_all(move |_| Saver::changed(&saver)));
+        }
+        let finisher = {
+            let saver = saver.clone();
+            Rc::new(move || saver.finish())
+        };
+        FINISHERS.with(|finishers| finishers.borrow_mut().push(finisher.clone()));
+
+        // `finish` is usually called from another thread, which has to ask this one to serialize the state
+        let (flush, flushed) = oneshot::channel();
+        executor::spawn_local(flushed.then(move |_| {
+            finisher();
+            Ok(())
+        }));
+        WRITERS.lock().unwrap().push(Writer { saves: writes, app_thread: thread::current().id(), flush, done });
+    }
+}
+
+/// Saves what is still waiting for its debounce, and waits until it is on disk. Called by the
+/// embedder right before it exits, since the writer threads die with it. From another thread, the
+/// app's thread has to render a frame to serialize its state, which it is woken up for.
+pub fn finish() {
+    finish_where(|_| true);
+}
+
+/// Finishes the apps saved from the threads `which` is true for
+fn finish_where<F: Fn(ThreadId) -> bool>(which: F) {
+    let writers = {
+        let mut writers = WRITERS.lock().unwrap();
+        let all = ::std::mem::replace(&mut *writers, vec![]);
+        let (finished, kept): (Vec<Writer>, Vec<Writer>) = all.into_iter().partition(|writer| which(writer.app_thread));
+        *writers = kept;
+        finished
+    };
+    let current = thread::current().id();
+    if writers.iter().any(|writer| writer.app_thread == current) {
+        let finishers = FINISHERS.with(|finishers| ::std::mem::replace(&mut *finishers.borrow_mut(), vec![]));
+        for finisher in finishers {
+            finisher();
+        }
+    }
+    for writer in writers {
+        if writer.app_thread != current && writer.flush.send(()).is_err() {
+            // The app's thread is gone, along with whatever it did not save
+            let _ = writer.saves.send(Save::Finish);
+        }
+        if writer.done.recv_timeout(Duration::from_secs(FINISH_TIMEOUT_SECS)).is_err() {
+            eprintln!("Gave up waiting for the app state to be saved");
+        }
+    }
+}
+
+/// Writes every save it is sent, until it is told to finish. The receiver is told once it did.
+fn spawn_writer(path: PathBuf) -> (mpsc::Sender<Save>, mpsc::Receiver<()>) {
+    let (sender, receiver) = mpsc::channel::<Save>();
+    let (done_sender, done) = mpsc::channel();
+    thread::spawn(move || {
+        // Stops once finished, or once the app is gone
+        while let Ok(Save::Contents(contents)) = receiver.recv() {
+            if let Err(err) = write(&path, &contents) {
+                eprintln!("Could not save the app state to {}: {}", path.display(), err);
+            }
+        }
+        let _ = done_sender.send(());
+    });
+    (sender, done)
+}
+
+/// Writes to a temporary file first, so a crash while saving doesn't lose the previous save
+fn write(path: &PathBuf, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temporary = path.with_extension("json.tmp");
+    {
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use std::time::SystemTime;
+    use std::time::UNIX_EPOCH;
+
+    use serde::Serialize;
+    use serde::Serializer;
+    use serde_json::Value;
+
+    use backend::headless::Harness;
+    use super::Error;
+    use super::Persist;
+    use super::finish_where;
+    use super::set_data_dir;
+
+    thread_local!(static SERIALIZED: Cell<u32> = Cell::new(0));
+
+    /// Counts how often it is serialized
+    #[derive(Clone, Debug, PartialEq, Deserialize)]
+    struct Count(u32);
+
+    impl Serialize for Count {
+        fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+            SERIALIZED.with(|serialized| serialized.set(serialized.get() + 1));
+            serializer.serialize_u32(self.0)
+        }
+    }
+
+    observable! {
+        #[derive(Clone, Debug, Serialize, Deserialize)]
+        struct State {
+            count: Count = Count(0),
+            title: String = String::new(),
+        }
+    }
+
+    thread_local!(static STATE: RefCell<State> = RefCell::new(State::new()));
+
+    fn render(state: &State) -> ::html::HtmlElement {
+        ::tags::p().text(state.get_count().0)
+    }
+
+    make_app_setup!{ pub fn app_setup() app_thread_state = STATE, render = render,
+                     persist = Persist::new("app", 2).debounce(Duration::from_millis(100)) }
+
+    /// An empty directory of its own for the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let dir = env::temp_dir().join(format!("ion-persist-{}-{}", name, nanos));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn save(path: &PathBuf, saved: Value) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(path).unwrap().write_all(saved.to_string().as_bytes()).unwrap();
+    }
+
+    fn saved(path: &PathBuf) -> Value {
+        let mut contents = String::new();
+        fs::File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+        ::serde_json::from_str(&contents).unwrap()
+    }
+
+    fn serialized() -> u32 {
+        SERIALIZED.with(|serialized| serialized.get())
+    }
+
+    fn set_count(count: u32) {
+        STATE.with(|state| state.borrow_mut().set_count(Count(count)));
+    }
+
+    fn finish_this_thread() {
+        let current = thread::current().id();
+        finish_where(|thread| thread == current);
+    }
+
+    #[test]
+    fn restores_nothing_if_nothing_was_saved() {
+        let path = temp_dir("nothing").join("state.json");
+        assert!(Persist::<State>::new("app", 1).path(path).restore().unwrap().is_none());
+    }
+
+    #[test]
+    fn restores_what_was_saved() {
+        let path = temp_dir("restore").join("state.json");
+        save(&path, json!({ "version": 1, "state": { "count": 3, "title": "Todo", "changes": null } }));
+        let state = Persist::<State>::new("app", 1).path(path).restore().unwrap().unwrap();
+        assert_eq!((state.get_count(), state.get_title().as_str()), (&Count(3), "Todo"));
+    }
+
+    #[test]
+    fn migrates_older_versions_in_order() {
+        let path = temp_dir("migrate").join("state.json");
+        save(&path, json!({ "version": 1, "state": { "clicks": 3, "changes": null } }));
+        let persist = Persist::<State>::new("app", 3)
+            .path(path)
+            .migrate(2, |state| state["title"] = json!(format!("{} clicks", state["count"])))
+            .migrate(1, |state| {
+                let clicks = state["clicks"].take();
+                state["count"] = clicks;
+            });
+        let state = persist.restore().unwrap().unwrap();
+        assert_eq!((state.get_count(), state.get_title().as_str()), (&Count(3), "3 clicks"));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let path = temp_dir("newer").join("state.json");
+        save(&path, json!({ "version": 3, "state": {} }));
+        match Persist::<State>::new("app", 2).path(path).restore() {
+            Err(Error::NewerVersion(3)) => {},
+            other => panic!("Expected NewerVersion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_versions_without_a_migration() {
+        let path = temp_dir("missing").join("state.json");
+        save(&path, json!({ "version": 1, "state": {} }));
+        match Persist::<State>::new("app", 3).path(path).migrate(2, |_| {}).restore() {
+            Err(Error::MissingMigration(1)) => {},
+            other => panic!("Expected MissingMigration, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn restores_before_the_first_frame() {
+        let dir = temp_dir("start");
+        save(&dir.join("app").join("state.json"), json!({ "version": 2, "state": { "count": 5, "title": "", "changes": null } }));
+        set_data_dir(Some(dir));
+        let app = Harness::new(app_setup);
+        assert_eq!(app.roots()[0].text(), "5");
+    }
+
+    #[test]
+    fn moves_a_broken_save_out_of_the_way() {
+        let dir = temp_dir("broken");
+        let path = dir.join("app").join("state.json");
+        save(&path, json!({ "version": 3, "state": {} }));
+        set_data_dir(Some(dir));
+        let app = Harness::new(app_setup);
+        assert_eq!(app.roots()[0].text(), "0");
+        assert!(!path.exists());
+        assert_eq!(saved(&path.with_extension("json.broken"))["version"], 3);
+    }
+
+    #[test]
+    fn serializes_once_the_state_stops_changing() {
+        let dir = temp_dir("debounce");
+        set_data_dir(Some(dir));
+        let mut app = Harness::new(app_setup);
+        let before = serialized();
+
+        for count in 1..5 {
+            set_count(count);
+            app.advance_time(Duration::from_millis(60));
+        }
+        // Never unchanged for 100ms yet
+        assert_eq!(serialized(), before);
+        app.advance_time(Duration::from_millis(40));
+        assert_eq!(serialized(), before + 1);
+        app.advance_time(Duration::from_secs(1));
+        assert_eq!(serialized(), before + 1);
+    }
+
+    #[test]
+    fn finish_saves_what_is_waiting_for_the_debounce() {
+        let dir = temp_dir("finish");
+        set_data_dir(Some(dir.clone()));
+        let mut app = Harness::new(app_setup);
+        set_count(1);
+        app.frame();
+        set_count(2);
+
+        finish_this_thread();
+        let saved = saved(&dir.join("app").join("state.json"));
+        assert_eq!((&saved["version"], &saved["state"]["count"]), (&json!(2), &json!(2)));
+        // Nothing is left to save once the debounce passes
+        let serialized_before = serialized();
+        app.advance_time(Duration::from_secs(1));
+        assert_eq!(serialized(), serialized_before);
+    }
+
+    #[test]
+    fn finish_from_another_thread_has_the_app_thread_save() {
+        let dir = temp_dir("finish-thread");
+        let (ready_sender, ready) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let app_dir = dir.clone();
+        let app_thread = thread::spawn(move || {
+            set_data_dir(Some(app_dir));
+            let mut app = Harness::new(app_setup);
+            set_count(7);
+            ready_sender.send(thread::current().id()).unwrap();
+            // The headless backend has no waker, so it renders frames until it is stopped
+            while stopped.try_recv().is_err() {
+                app.frame();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let app_thread_id = ready.recv().unwrap();
+        finish_where(|thread| thread == app_thread_id);
+        assert_eq!(saved(&dir.join("app").join("state.json"))["state"]["count"], 7);
+        stop.send(()).unwrap();
+        app_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+use std::fmt::Write;
+
+use html::HtmlElement;
+use html::HtmlNode;
+use html::Property;
+
+/// Elements that can't have children, and are written without a closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
+
+fn escape_text(text: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    for c in text.chars() {
+        match c {
+            '&' => f.write_str("&amp;")?,
+            '<' => f.write_str("&lt;")?,
+            '>' => f.write_str("&gt;")?,
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+fn escape_attribute(value: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    for c in value.chars() {
+        match c {
+            '&' => f.write_str("&amp;")?,
+            '"' => f.write_str("&quot;")?,
+            '<' => f.write_str("&lt;")?,
+            '>' => f.write_str("&gt;")?,
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_attribute(name: &str, value: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, " {}=\"", name)?;
+    escape_attribute(value, f)?;
+    f.write_char('"')
+}
+
+/// Writes the markup Servo would end up with after rendering the node. Listeners are left out, and
+/// so are the ids of unkeyed elements, since those depend on where the node ends up in the tree.
+impl fmt::Display for HtmlNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HtmlNode::Element(ref elem) => elem.fmt(f),
+            HtmlNode::Memo(ref elem) => elem.fmt(f),
+            HtmlNode::Text(ref text) => escape_text(text, f),
+            HtmlNode::Fragment(ref children) => {
+                for child in children {
+                    child.fmt(f)?;
+                }
+                Ok(())
+            },
+            // Rendered with a new instance, since the tree is not being rendered into a DOM
+            HtmlNode::Component(ref mount) => (mount.render)(None).fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for HtmlElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{}", self.tag)?;
+        if self.key.is_some() {
+            write_attribute("id", &self.id, f)?;
+        }
+        if !self.class.is_empty() {
+            write_attribute("class", &self.class, f)?;
+        }
+        if !self.style.is_empty() {
+            write_attribute("style", &self.style, f)?;
+        }
+
+        // A textarea's value is its contents rather than an attribute
+        let is_textarea = self.tag == "textarea";
+
+        // Sorted, so the same tree always gives the same string
+        let mut names: Vec<&String> = self.attributes.keys()
+            .chain(self.properties.keys().filter(|name| !self.attributes.contains_key(*name)))
+            .collect();
+        names.sort();
+        for name in names {
+            match self.properties.get(name) {
+                Some(&Property::Text(_)) if is_textarea && name == "value" => {},
+                Some(&Property::Text(ref value)) => write_attribute(name, value, f)?,
+                Some(&Property::Bool(true)) => write!(f, " {}", name)?,
+                Some(&Property::Bool(false)) => {},
+                None => write_attribute(name, &self.attributes[name], f)?,
+            }
+        }
+        f.write_char('>')?;
+
+        if VOID_ELEMENTS.contains(&self.tag.as_str()) {
+            return Ok(());
+        }
+        match self.properties.get("value") {
+            Some(&Property::Text(ref value)) if is_textarea => escape_text(value, f)?,
+            _ => for child in &self.children {
+                child.fmt(f)?;
+            },
+        }
+        write!(f, "</{}>", self.tag)
+    }
+}
+
+impl HtmlNode {
+    /// Serializes the tree to HTML, for snapshot tests, static pages or logging
+    pub fn to_html(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl HtmlElement {
+    /// Serializes the tree to HTML, for snapshot tests, static pages or logging
+    pub fn to_html(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use html::HtmlNode;
+    use html::fragment;
+    use tags::*;
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(p().text("a < b && c > d \"quoted\"").to_html(), "<p>a &lt; b &amp;&amp; c &gt; d \"quoted\"</p>");
+    }
+
+    #[test]
+    fn escapes_attributes() {
+        let elem = a().href("/search?q=a&b").title("\"Hi\" <there>").class("x&y").style("content: \"<\"");
+        assert_eq!(
+            elem.to_html(),
+            r#"<a class="x&amp;y" style="content: &quot;&lt;&quot;" href="/search?q=a&amp;b" title="&quot;Hi&quot; &lt;there&gt;"></a>"#
+        );
+    }
+
+    #[test]
+    fn writes_void_elements_without_a_closing_tag() {
+        assert_eq!(div().child(br()).child(img().src("a.png")).child(hr()).to_html(), r#"<div><br><img src="a.png"><hr></div>"#);
+        // Even when something tried to give them children
+        assert_eq!(input().child(p()).to_html(), "<input>");
+    }
+
+    #[test]
+    fn writes_the_value_of_a_textarea_as_its_contents() {
+        assert_eq!(textarea().value("a < b").rows(3).to_html(), r#"<textarea rows="3">a &lt; b</textarea>"#);
+        assert_eq!(input().value("a < b").to_html(), r#"<input value="a &lt; b">"#);
+    }
+
+    #[test]
+    fn writes_boolean_properties_only_when_true() {
+        assert_eq!(input().checked(true).disabled(false).to_html(), "<input checked>");
+        assert_eq!(option().selected(false).text("One").to_html(), "<option>One</option>");
+    }
+
+    #[test]
+    fn sorts_attributes_and_properties_together() {
+        let elem = input().placeholder("Name").value("Milk").name("item").autofocus(true).attr("data-z", 1);
+        assert_eq!(elem.to_html(), r#"<input autofocus data-z="1" name="item" placeholder="Name" value="Milk">"#);
+    }
+
+    #[test]
+    fn writes_fragments_as_their_children() {
+        let node = fragment(vec![HtmlNode::from(p().text("a")), HtmlNode::Text("b".to_string()), fragment(vec![span().text("c")])]);
+        assert_eq!(node.to_html(), "<p>a</p>b<span>c</span>");
+        assert_eq!(div().child(node).to_html(), "<div><p>a</p>b<span>c</span></div>");
+    }
+
+    #[test]
+    fn only_writes_the_ids_of_keyed_elements() {
+        let elem = ul().child(li().key("milk").text("Milk")).child(li().text("Eggs"));
+        assert_eq!(elem.to_html(), r#"<ul><li id="unique_key_milk">Milk</li><li>Eggs</li></ul>"#);
+    }
+}
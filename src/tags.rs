@@ -0,0 +1,68 @@
+use html::Event;
+use html::HtmlElement;
+use html::Property;
+
+macro_rules! tags {
+    ($($tag:ident)*) => {
+        $(
+            #[allow(dead_code)]
+            pub fn $tag() -> HtmlElement { HtmlElement::new(stringify!($tag)) }
+        )*
+    }
+}
+
+tags! {
+    a abbr article aside b blockquote br button canvas code dd div dl dt em fieldset figure footer form
+    h1 h2 h3 h4 h5 h6 header hr i img input label legend li main nav ol option p pre section select small
+    span strong sub sup table tbody td textarea tfoot th thead tr u ul
+}
+
+macro_rules! attributes {
+    ($($name:ident)*) => {
+        impl HtmlElement {
+            $(
+                #[allow(dead_code)]
+                pub fn $name<T: ToString>(self, value: T) -> HtmlElement { self.attr(stringify!($name), value) }
+            )*
+        }
+    }
+}
+
+macro_rules! properties {
+    ($($name:ident)*) => {
+        impl HtmlElement {
+            $(
+                #[allow(dead_code)]
+                pub fn $name<T: Into<Property>>(self, value: T) -> HtmlElement { self.prop(stringify!($name), value) }
+            )*
+        }
+    }
+}
+
+macro_rules! events {
+    ($($name:ident => $event:expr),*) => {
+        impl HtmlElement {
+            $(
+                #[allow(dead_code)]
+                pub fn $name<F: Fn(&Event) + 'static>(self, handler: F) -> HtmlElement { self.on($event, handler) }
+            )*
+        }
+    }
+}
+
+// These make up the attributes `html!` accepts, other than class, style and key which are set directly
+attributes! {
+    accept action alt autocomplete cols colspan dir download draggable enctype headers height href hreflang
+    lang list max maxlength method min minlength name pattern placeholder rel role rows rowspan scope size
+    src srcset start step tabindex target title width wrap
+}
+
+// Boolean attributes are there or not, so `false` has to remove them rather than set them to "false"
+properties! { value checked disabled selected autofocus hidden multiple readonly required }
+
+events! {
+    onclick => "click", ondblclick => "dblclick", oninput => "input", onchange => "change",
+    onkeydown => "keydown", onkeyup => "keyup", onkeypress => "keypress", onfocus => "focus", onblur => "blur",
+    onsubmit => "submit", onmousedown => "mousedown", onmouseup => "mouseup", onmousemove => "mousemove",
+    onmouseover => "mouseover", onmouseout => "mouseout"
+}
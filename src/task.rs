@@ -0,0 +1,259 @@
+//! Runs slow work, like reading files, on a pool of worker threads, and hands the result back to the
+//! thread the app runs on:
+//!
+//! ```ignore
+//! button().text("Open").on("click", |_| {
+//!     task::spawn(|| fs::File::open("list.json").and_then(read_items), |items| update::send(Msg::Loaded(items)));
+//! })
+//! ```
+//!
+//! `done` runs right before the next frame, so it can send messages or change the app state like any
+//! handler. The app state itself stays on its own thread, so `work` can only use what is moved into it.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+use futures::unsync::oneshot;
+
+/// How many tasks can run at the same time
+const WORKERS: usize = 4;
+
+lazy_static! {
+    /// Set by `set_waker`
+    static ref WAKER: Mutex<Option<Arc<Fn() + Send + Sync>>> = Mutex::new(None);
+}
+
+thread_local!(static POOL: RefCell<Option<Pool>> = RefCell::new(None));
+
+/// Runs a task on a worker. Boxed `FnOnce`s cannot be called, so it is taken out of an `Option`.
+type Job = Box<FnMut() + Send>;
+
+struct Pool {
+    jobs: mpsc::Sender<Job>,
+    results: mpsc::Receiver<(usize, thread::Result<Box<Any + Send>>)>,
+    results_sender: mpsc::Sender<(usize, thread::Result<Box<Any + Send>>)>,
+    next_id: usize,
+    /// The `done` callbacks of the tasks that are still running
+    running: HashMap<usize, Box<FnMut(Box<Any>)>>,
+}
+
+impl Pool {
+    fn new() -> Pool {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..WORKERS {
+            let receiver = receiver.clone();
+            thread::Builder::new().name(format!("ion task {}", i)).spawn(move || loop {
+                // Only waiting for a job is locked, not running it
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    Ok(mut job) => job(),
+                    Err(_) => return,
+                }
+            }).expect("Could not start a worker thread");
+        }
+        let (results_sender, results) = mpsc::channel();
+        Pool { jobs, results, results_sender, next_id: 0, running: HashMap::new() }
+    }
+}
+
+/// Called by the backend with a way to ask for a frame from any thread, which ion calls whenever a
+/// task finishes, a future is woken up or a timer is due. Without one, that work waits for the next
+/// frame something else asks for, which is what the headless backend wants.
+pub fn set_waker<F: Fn() + Send + Sync + 'static>(wake: F) {
+    *WAKER.lock().unwrap() = Some(Arc::new(wake));
+}
+
+/// Asks the backend for a frame, from any thread
+pub(crate) fn wake() {
+    // Not locked while it runs, so a waker that ends up waking again doesn't deadlock
+    let waker = WAKER.lock().unwrap().clone();
+    if let Some(waker) = waker {
+        waker();
+    }
+}
+
+/// Runs `work` on a worker thread, then `done` with what it returned on this thread, before the next
+/// frame. If `work` panics, `done` is never called.
+pub fn spawn<T, W, D>(work: W, done: D)
+    where T: Send + 'static, W: FnOnce() -> T + Send + 'static, D: FnOnce(T) + 'static {
+    POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        let pool = pool.get_or_insert_with(Pool::new);
+        let id = pool.next_id;
+        pool.next_id += 1;
+
+        let mut done = Some(done);
+        pool.running.insert(id, Box::new(move |result: Box<Any>| {
+            let result = *result.downcast::<T>().expect("A task returned the wrong type");
+            (done.take().unwrap())(result)
+        }));
+
+        let mut work = Some(work);
+        let results = pool.results_sender.clone();
+        pool.jobs.send(Box::new(move || {
+            let work = work.take().unwrap();
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| Box::new(work()) as Box<Any + Send>));
+            let _ = results.send((id, result));
+            wake();
+        })).expect("The worker threads are gone");
+    })
+}
+
+/// Like `spawn`, but the result is a future for `executor::spawn_local`. It fails with `Canceled` if
+/// `work` panics.
+///
+/// ```ignore
+/// executor::spawn_local(task::run(|| load("list.json")).then(|items| {
+///     update::send(Msg::Loaded(items.unwrap_or_default()));
+///     Ok(())
+/// }));
+/// ```
+pub fn run<T, W>(work: W) -> oneshot::Receiver<T> where T: Send + 'static, W: FnOnce() -> T + Send + 'static {
+    let (sender, receiver) = oneshot::channel();
+    spawn(work, move |result| { let _ = sender.send(result); });
+    receiver
+}
+
+/// Whether any task has not been handed back yet
+pub fn running() -> bool {
+    POOL.with(|pool| pool.borrow().as_ref().map_or(false, |pool| !pool.running.is_empty()))
+}
+
+/// Calls `done` for every task that finished. Used by `html::render_frame`.
+pub(crate) fn finish() {
+    while let Some((id, result)) = next_result(false) {
+        hand_back(id, result);
+    }
+}
+
+/// Blocks until every task finished, and calls their `done`. Used by the headless backend, so tests
+/// don't depend on how fast the workers are.
+pub fn wait() {
+    while running() {
+        if let Some((id, result)) = next_result(true) {
+            hand_back(id, result);
+        }
+    }
+}
+
+fn next_result(block: bool) -> Option<(usize, thread::Result<Box<Any + Send>>)> {
+    POOL.with(|pool| {
+        let pool = pool.borrow();
+        let pool = pool.as_ref()?;
+        if block { pool.results.recv().ok() } else { pool.results.try_recv().ok() }
+    })
+}
+
+/// The pool is not borrowed while `done` runs, so it can spawn more tasks
+fn hand_back(id: usize, result: thread::Result<Box<Any + Send>>) {
+    let done = POOL.with(|pool| pool.borrow_mut().as_mut().and_then(|pool| pool.running.remove(&id)));
+    match (done, result) {
+        (Some(mut done), Ok(result)) => done(result),
+        (Some(_), Err(_)) => eprintln!("A task panicked, so its result is dropped"),
+        (None, _) => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use backend::headless::Harness;
+    use html::HtmlNode;
+    use html::RENDER;
+    use tags::*;
+    use super::running;
+    use super::spawn;
+
+    thread_local!(static LOADED: RefCell<Option<String>> = RefCell::new(None));
+
+    fn render() -> Option<HtmlNode> {
+        let loaded = LOADED.with(|loaded| loaded.borrow().clone());
+        Some(p().text(loaded.unwrap_or_else(|| "Loading".to_string())).into())
+    }
+
+    fn setup() {
+        RENDER.with(|root| root.set(Some(render)));
+    }
+
+    fn text(app: &Harness) -> String {
+        app.roots()[0].text()
+    }
+
+    #[test]
+    fn hands_the_result_back_on_this_thread_before_the_frame() {
+        let mut app = Harness::new(setup);
+        let app_thread = thread::current().id();
+        spawn(move || {
+            assert!(thread::current().id() != app_thread);
+            "Loaded".to_string()
+        }, move |result| {
+            assert_eq!(thread::current().id(), app_thread);
+            LOADED.with(|loaded| *loaded.borrow_mut() = Some(result));
+        });
+        assert!(running());
+        assert_eq!(text(&app), "Loading");
+
+        // Rendered by the first frame after the worker is done, however long that takes
+        for _ in 0..1000 {
+            app.frame();
+            if !running() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(text(&app), "Loaded");
+    }
+
+    #[test]
+    fn finish_tasks_waits_for_every_task() {
+        let mut app = Harness::new(setup);
+        let done = Rc::new(Cell::new(0));
+        for i in 0..10 {
+            let done = done.clone();
+            spawn(move || { thread::sleep(Duration::from_millis(10)); i }, move |i| done.set(done.get() + i));
+        }
+        app.finish_tasks();
+        assert_eq!(done.get(), 45);
+        assert!(!running());
+    }
+
+    #[test]
+    fn drops_a_task_that_panicked() {
+        let mut app = Harness::new(setup);
+        let done = Rc::new(Cell::new(false));
+        {
+            let done = done.clone();
+            spawn(|| -> String { panic!("while loading") }, move |_| done.set(true));
+        }
+        app.finish_tasks();
+        assert!(!done.get());
+        assert!(!running());
+
+        // The workers are still there for the next one
+        spawn(|| "Loaded".to_string(), |result| LOADED.with(|loaded| *loaded.borrow_mut() = Some(result)));
+        app.finish_tasks();
+        assert_eq!(text(&app), "Loaded");
+    }
+
+    #[test]
+    fn done_can_spawn_more_tasks() {
+        let mut app = Harness::new(setup);
+        spawn(|| 1, |first| spawn(move || first + 1, |second: u32| {
+            LOADED.with(|loaded| *loaded.borrow_mut() = Some(second.to_string()));
+        }));
+        app.finish_tasks();
+        assert_eq!(text(&app), "2");
+    }
+}
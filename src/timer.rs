@@ -0,0 +1,320 @@
+//! Runs handlers on the thread the app runs on after a delay, or over and over, like `setTimeout` and
+//! `setInterval`:
+//!
+//! ```ignore
+//! let clock = timer::every(Duration::from_secs(1), || update::send(Msg::Tick));
+//! button().text("Stop").on("click", move |_| clock.cancel())
+//! ```
+//!
+//! Handlers run right before a frame, so whatever they change in the app state is rendered by it. A
+//! thread of its own sleeps until the next timer is due, then calls the waker the backend gave
+//! `task::set_waker`, which asks for that frame.
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::unsync::oneshot;
+
+use task;
+
+thread_local!(static TIMERS: RefCell<Option<Timers>> = RefCell::new(None));
+/// How far the clock was moved ahead by `advance`
+thread_local!(static OFFSET: Cell<Duration> = Cell::new(Duration::from_secs(0)));
+
+struct Timers {
+    timers: HashMap<usize, Timer>,
+    next_id: usize,
+    /// Tells the sleeping thread when a timer is due
+    deadlines: mpsc::Sender<Instant>,
+}
+
+struct Timer {
+    due: Instant,
+    /// How long to wait until running it again, if it repeats
+    interval: Option<Duration>,
+    /// Taken out while it runs
+    handler: Option<Box<FnMut()>>,
+}
+
+/// Stops a timer from running again. Dropping it leaves the timer running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handle(usize);
+
+impl Handle {
+    /// Does nothing if the timer already ran, or was cancelled
+    pub fn cancel(self) {
+        TIMERS.with(|timers| {
+            if let Some(ref mut timers) = *timers.borrow_mut() {
+                timers.timers.remove(&self.0);
+            }
+        })
+    }
+}
+
+fn with_timers<R, F: FnOnce(&mut Timers) -> R>(f: F) -> R {
+    TIMERS.with(|timers| {
+        let mut timers = timers.borrow_mut();
+        let timers = timers.get_or_insert_with(|| Timers { timers: HashMap::new(), next_id: 0, deadlines: spawn_sleeper() });
+        f(timers)
+    })
+}
+
+/// Calls the waker whenever one of the deadlines it is sent passes
+fn spawn_sleeper() -> mpsc::Sender<Instant> {
+    let (sender, receiver) = mpsc::channel::<Instant>();
+    thread::Builder::new().name("ion timers".to_string()).spawn(move || {
+        let mut deadlines = BinaryHeap::new();
+        loop {
+            let now = Instant::now();
+            let mut passed = false;
+            while deadlines.peek().map_or(false, |&Reverse(deadline)| deadline <= now) {
+                deadlines.pop();
+                passed = true;
+            }
+            if passed {
+                task::wake();
+            }
+            let deadline = match deadlines.peek() {
+                Some(&Reverse(next)) => match receiver.recv_timeout(next - now) {
+                    Ok(deadline) => deadline,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                },
+                None => match receiver.recv() {
+                    Ok(deadline) => deadline,
+                    Err(_) => return,
+                },
+            };
+            deadlines.push(Reverse(deadline));
+        }
+    }).expect("Could not start the timer thread");
+    sender
+}
+
+/// The time timers go by, which is only ahead of the real one in tests
+fn now() -> Instant {
+    Instant::now() + OFFSET.with(|offset| offset.get())
+}
+
+/// Moves the clock timers go by ahead, so tests don't have to wait for them. The timers that became
+/// due run in the next frame. Used by the headless backend.
+pub(crate) fn advance(by: Duration) {
+    OFFSET.with(|offset| offset.set(offset.get() + by));
+}
+
+fn schedule(due: Instant, interval: Option<Duration>, handler: Box<FnMut()>) -> Handle {
+    with_timers(|timers| {
+        let id = timers.next_id;
+        timers.next_id += 1;
+        timers.timers.insert(id, Timer { due, interval, handler: Some(handler) });
+        let _ = timers.deadlines.send(due);
+        Handle(id)
+    })
+}
+
+/// Runs `handler` once `delay` passed
+pub fn after<F: FnOnce() + 'static>(delay: Duration, handler: F) -> Handle {
+    let mut handler = Some(handler);
+    schedule(now() + delay, None, Box::new(move || (handler.take().unwrap())()))
+}
+
+/// Runs `handler` every `interval`, starting once the first one passed. Intervals that were missed
+/// while the app was busy are skipped, rather than run all at once.
+pub fn every<F: FnMut() + 'static>(interval: Duration, handler: F) -> Handle {
+    schedule(now() + interval, Some(interval), Box::new(handler))
+}
+
+/// A future for `executor::spawn_local` that completes once `delay` passed
+pub fn delay(delay: Duration) -> oneshot::Receiver<()> {
+    let (sender, receiver) = oneshot::channel();
+    after(delay, move || { let _ = sender.send(()); });
+    receiver
+}
+
+/// Runs the handlers of the timers that are due, earliest first. Used by `html::render_frame`.
+pub(crate) fn fire() {
+    let now = now();
+    let due = TIMERS.with(|timers| match *timers.borrow() {
+        Some(ref timers) => {
+            let mut due: Vec<(Instant, usize)> = timers.timers.iter()
+                .filter(|&(_, timer)| timer.due <= now)
+                .map(|(&id, timer)| (timer.due, id))
+                .collect();
+            due.sort();
+            due
+        },
+        None => vec![],
+    });
+
+    for (_, id) in due {
+        // Not borrowed while the handler runs, so it can start or cancel timers, including its own
+        let handler = with_timers(|timers| timers.timers.get_mut(&id).and_then(|timer| timer.handler.take()));
+        let mut handler = match handler {
+            Some(handler) => handler,
+            // Cancelled by an earlier handler
+            None => continue,
+        };
+        handler();
+        with_timers(|timers| {
+            let interval = match timers.timers.get(&id) {
+                Some(timer) => timer.interval,
+                None => return,
+            };
+            match interval {
+                Some(interval) => {
+                    let timer = timers.timers.get_mut(&id).unwrap();
+                    timer.due = if timer.due + interval > now { timer.due + interval } else { now + interval };
+                    timer.handler = Some(handler);
+                    let _ = timers.deadlines.send(timer.due);
+                },
+                None => { timers.timers.remove(&id); },
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use backend::headless::Harness;
+    use html::HtmlNode;
+    use html::RENDER;
+    use super::Handle;
+    use super::after;
+    use super::every;
+
+    fn nothing() -> Option<HtmlNode> { None }
+
+    fn setup() {
+        RENDER.with(|root| root.set(Some(nothing)));
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// A handler that counts how often it ran, and the count
+    fn counter() -> (Rc<Cell<u32>>, Box<Fn()>) {
+        let count = Rc::new(Cell::new(0));
+        let counted = count.clone();
+        (count, Box::new(move || counted.set(counted.get() + 1)))
+    }
+
+    #[test]
+    fn after_runs_once_it_is_due() {
+        let mut app = Harness::new(setup);
+        let (count, handler) = counter();
+        after(ms(100), move || handler());
+
+        app.advance_time(ms(99));
+        assert_eq!(count.get(), 0);
+        app.advance_time(ms(1));
+        assert_eq!(count.get(), 1);
+        app.advance_time(ms(1000));
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn every_repeats_and_skips_missed_intervals() {
+        let mut app = Harness::new(setup);
+        let (count, handler) = counter();
+        every(ms(100), move || handler());
+
+        app.advance_time(ms(100));
+        app.advance_time(ms(100));
+        assert_eq!(count.get(), 2);
+        // Busy for a while, so only one of the missed ones runs
+        app.advance_time(ms(450));
+        assert_eq!(count.get(), 3);
+        app.advance_time(ms(99));
+        assert_eq!(count.get(), 3);
+        app.advance_time(ms(1));
+        assert_eq!(count.get(), 4);
+    }
+
+    #[test]
+    fn runs_due_timers_earliest_first() {
+        let mut app = Harness::new(setup);
+        let order = Rc::new(RefCell::new(vec![]));
+        for &(delay, name) in &[(300, "c"), (100, "a"), (200, "b")] {
+            let order = order.clone();
+            after(ms(delay), move || { order.borrow_mut().push(name); });
+        }
+        app.advance_time(ms(300));
+        assert_eq!(*order.borrow(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn cancelled_timers_do_not_run() {
+        let mut app = Harness::new(setup);
+        let (once, handler) = counter();
+        after(ms(100), move || handler()).cancel();
+        let (repeated, handler) = counter();
+        let handle = every(ms(100), move || handler());
+
+        app.advance_time(ms(100));
+        assert_eq!((once.get(), repeated.get()), (0, 1));
+        handle.cancel();
+        // Cancelling twice does nothing
+        handle.cancel();
+        app.advance_time(ms(1000));
+        assert_eq!((once.get(), repeated.get()), (0, 1));
+    }
+
+    #[test]
+    fn handlers_can_cancel_their_own_timer() {
+        let mut app = Harness::new(setup);
+        let count = Rc::new(Cell::new(0));
+        let own: Rc<Cell<Option<Handle>>> = Rc::new(Cell::new(None));
+        let handle = {
+            let (count, own) = (count.clone(), own.clone());
+            every(ms(100), move || {
+                count.set(count.get() + 1);
+                if count.get() == 2 {
+                    own.get().unwrap().cancel();
+                }
+            })
+        };
+        own.set(Some(handle));
+
+        for _ in 0..5 {
+            app.advance_time(ms(100));
+        }
+        assert_eq!(count.get(), 2);
+    }
+
+    #[test]
+    fn handlers_can_cancel_timers_due_in_the_same_frame() {
+        let mut app = Harness::new(setup);
+        let (count, handler) = counter();
+        let later = after(ms(200), move || handler());
+        after(ms(100), move || later.cancel());
+
+        // Both are due, but the earlier one runs first and cancels the other
+        app.advance_time(ms(200));
+        assert_eq!(count.get(), 0);
+    }
+
+    #[test]
+    fn handlers_can_start_timers() {
+        let mut app = Harness::new(setup);
+        let (count, handler) = counter();
+        after(ms(100), move || { after(ms(100), move || handler()); });
+
+        app.advance_time(ms(100));
+        assert_eq!(count.get(), 0);
+        app.advance_time(ms(100));
+        assert_eq!(count.get(), 1);
+    }
+}
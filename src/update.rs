@@ -0,0 +1,68 @@
+//! Event handlers send messages instead of changing the app state themselves, and one `update`
+//! function applies them:
+//!
+//! ```ignore
+//! enum Msg { Add, Rename(String) }
+//!
+//! fn update(state: &mut AppState, msg: Msg) {
+//!     match msg {
+//!         Msg::Add => state.get_items_mut().push(Item::new()),
+//!         Msg::Rename(name) => state.set_name(name),
+//!     }
+//! }
+//!
+//! button().text("+").on_message("click", |_| Msg::Add)
+//! ```
+//!
+//! Pass `update = update` to `make_app_setup!`, and the messages are applied right before the next frame.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+thread_local!(static MESSAGES: RefCell<VecDeque<Queued>> = RefCell::new(VecDeque::new()));
+thread_local!(static IN_EVENT: Cell<bool> = Cell::new(false));
+
+struct Queued {
+    msg: Box<Any>,
+    /// Whether it was sent by an event handler, rather than by a task, timer or `update` itself
+    from_event: bool,
+}
+
+/// Queues a message for the app's `update` function. Handlers run by the backend already ask for a
+/// frame, so the message is applied as soon as they return.
+pub fn send<M: 'static>(msg: M) {
+    let from_event = IN_EVENT.with(|in_event| in_event.get());
+    MESSAGES.with(|messages| messages.borrow_mut().push_back(Queued { msg: Box::new(msg), from_event }));
+}
+
+/// Runs an event handler, so the messages it sends can be told apart from the others
+pub(crate) fn in_event<R, F: FnOnce() -> R>(f: F) -> R {
+    let outer = IN_EVENT.with(|in_event| in_event.replace(true));
+    let result = f();
+    IN_EVENT.with(|in_event| in_event.set(outer));
+    result
+}
+
+/// Forgets the queued messages that event handlers sent, and keeps the rest
+pub(crate) fn drop_event_messages() {
+    MESSAGES.with(|messages| messages.borrow_mut().retain(|queued| !queued.from_event));
+}
+
+/// Applies the queued messages in the order they were sent, including ones sent by `update` itself
+pub fn process<S, M: 'static>(state: &mut S, update: fn(&mut S, M)) {
+    while let Some(msg) = next_message::<M>() {
+        update(state, msg);
+    }
+}
+
+/// Takes the oldest queued message. The queue is not borrowed while it is handled, so handling it
+/// can send more messages.
+pub(crate) fn next_message<M: 'static>() -> Option<M> {
+    let queued = MESSAGES.with(|messages| messages.borrow_mut().pop_front())?;
+    match queued.msg.downcast::<M>() {
+        Ok(msg) => Some(*msg),
+        Err(_) => panic!("A message was sent that is not of the type `update` takes"),
+    }
+}
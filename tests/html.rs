@@ -0,0 +1,64 @@
+//! Checks what the `html!` macro builds, through the HTML it serializes to
+
+#[macro_use]
+extern crate ion;
+
+use ion::html::HtmlElement;
+use ion::tags::*;
+
+#[test]
+fn nests_elements() {
+    let elem = html!{
+        <div class="row">
+            <p>{"Hi"}</p>
+            <div><span>{"there"}</span>{"!"}</div>
+        </div>
+    };
+    assert_eq!(elem.to_html(), r#"<div class="row"><p>Hi</p><div><span>there</span>!</div></div>"#);
+}
+
+#[test]
+fn adds_children_from_an_iterator() {
+    let items = vec!["Milk", "Eggs"];
+    let elem = html!{ <ul>{for items.iter().map(|&item| html!{ <li>{item}</li> })}</ul> };
+    assert_eq!(elem.to_html(), "<ul><li>Milk</li><li>Eggs</li></ul>");
+
+    let none: Vec<HtmlElement> = vec![];
+    assert_eq!(html!{ <ul>{for none}</ul> }.to_html(), "<ul></ul>");
+}
+
+#[test]
+fn leaves_out_missing_optional_children() {
+    let shown = Some(html!{ <b>{"shown"}</b> });
+    let hidden: Option<HtmlElement> = None;
+    let elem = html!{ <p>{shown}{hidden}{Some("text")}</p> };
+    assert_eq!(elem.to_html(), "<p><b>shown</b>text</p>");
+}
+
+#[test]
+fn passes_hyphenated_attributes_through() {
+    let elem = html!{ <div data-item-id=3 aria-label="Close"></div> };
+    assert_eq!(elem.to_html(), r#"<div aria-label="Close" data-item-id="3"></div>"#);
+}
+
+#[test]
+fn sets_type_and_for() {
+    let elem = html!{ <label for="name">{"Name"}<input type="text" name="name" /></label> };
+    assert_eq!(elem.to_html(), r#"<label for="name">Name<input name="name" type="text"></label>"#);
+}
+
+#[test]
+fn closes_self_closing_tags() {
+    assert_eq!(html!{ <br/> }.to_html(), "<br>");
+    let elem = html!{ <p>{"a"}<br/>{"b"}<hr /></p> };
+    assert_eq!(elem.to_html(), "<p>a<br>b<hr></p>");
+}
+
+#[test]
+fn leaves_out_false_boolean_attributes() {
+    let elem = html!{ <input autofocus=false hidden=false readonly=true required=true disabled=false /> };
+    assert_eq!(elem.to_html(), "<input readonly required>");
+
+    let elem = select().multiple(true).child(option().selected(false).text("One"));
+    assert_eq!(elem.to_html(), "<select multiple><option>One</option></select>");
+}
@@ -0,0 +1,201 @@
+//! App state declared with `observable!` and `#[derive(Observable)]`, the way apps use them
+
+#[macro_use]
+extern crate ion;
+#[macro_use]
+extern crate ion_derive;
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ion::observable::Changes;
+use ion::observable::Observable;
+
+thread_local!(static HEADINGS_COMPUTED: Cell<u32> = Cell::new(0));
+
+observable! {
+    #[derive(Clone, Debug)]
+    struct Todo {
+        items: Vec<String> = vec![],
+        title: String = "Todo".to_string(),
+        computed count(items): usize = items.len(),
+        computed heading(title, items): String = {
+            HEADINGS_COMPUTED.with(|computed| computed.set(computed.get() + 1));
+            format!("{} ({})", title, items.len())
+        },
+    }
+}
+
+mod lists {
+    observable! {
+        /// A list of anything, with a name
+        #[derive(Clone, Debug)]
+        pub struct List<T> where T: Clone {
+            pub items: Vec<T> = vec![],
+            pub name: String,
+            pub(crate) limit: usize,
+            hidden: bool = false,
+        }
+    }
+
+    impl<T: Clone> List<T> {
+        pub fn hide(&mut self) {
+            self.set_hidden(true);
+        }
+
+        pub fn is_hidden(&self) -> bool {
+            *self.get_hidden()
+        }
+    }
+}
+
+#[derive(Observable)]
+struct Counter {
+    count: u32,
+    changes: Changes<Counter>,
+}
+
+#[test]
+fn accessors() {
+    let mut todo = Todo::new();
+    assert_eq!(todo.get_title(), "Todo");
+    assert!(todo.get_items().is_empty());
+
+    todo.set_title("Groceries".to_string());
+    todo.get_items_mut().push("Milk".to_string());
+    assert_eq!(todo.get_title(), "Groceries");
+    assert_eq!(*todo.get_items(), vec!["Milk".to_string()]);
+}
+
+#[test]
+fn changes_are_tracked_per_field_until_the_frame_is_rendered() {
+    let mut todo = Todo::new();
+    assert!(todo.changes().has_changed());
+    todo.changes_mut().finish_frame();
+    assert!(!todo.changes().has_changed());
+
+    todo.set_title("Groceries".to_string());
+    assert!(todo.changes().has_changed());
+    assert!(todo.changes().field_changed("title"));
+    assert!(!todo.changes().field_changed("items"));
+    assert_eq!(todo.changes().changed_fields(), vec!["title"]);
+
+    let before = todo.clone();
+    let revision = todo.changes().revision("items");
+    todo.get_items_mut().push("Milk".to_string());
+    assert!(todo.changes().revision("items") > revision);
+    assert_eq!(todo.changes().changed_since(before.changes()), vec!["items"]);
+
+    todo.changes_mut().finish_frame();
+    assert!(todo.changes().changed_fields().is_empty());
+}
+
+#[test]
+fn field_mut_only_counts_writes() {
+    let mut todo = Todo::new();
+    todo.changes_mut().finish_frame();
+
+    {
+        let items = todo.get_items_mut();
+        assert_eq!(items.len(), 0);
+    }
+    assert!(!todo.changes().has_changed());
+
+    todo.get_items_mut().push("Milk".to_string());
+    assert_eq!(todo.changes().changed_fields(), vec!["items"]);
+}
+
+#[test]
+fn subscribers_hear_about_their_field() {
+    let mut todo = Todo::new();
+    let titles = Rc::new(RefCell::new(vec![]));
+    let any = Rc::new(Cell::new(0));
+    {
+        let titles = titles.clone();
+        todo.subscribe_title(move |todo| titles.borrow_mut().push(todo.get_title().clone()));
+    }
+    {
+        let any = any.clone();
+        todo.changes_mut().subscribe_all(move |_| any.set(any.get() + 1));
+    }
+
+    todo.set_title("Groceries".to_string());
+    todo.get_items_mut().push("Milk".to_string());
+    // Reading through get_x_mut is not a change
+    let _ = todo.get_items_mut().len();
+
+    assert_eq!(*titles.borrow(), vec!["Groceries".to_string()]);
+    assert_eq!(any.get(), 2);
+}
+
+#[test]
+fn subscribers_see_the_state_after_the_write() {
+    let mut todo = Todo::new();
+    let seen = Rc::new(Cell::new(0));
+    {
+        let seen = seen.clone();
+        todo.subscribe_items(move |todo| seen.set(todo.get_items().len()));
+    }
+    todo.get_items_mut().push("Milk".to_string());
+    assert_eq!(seen.get(), 1);
+}
+
+#[test]
+fn generics_visibility_and_fields_without_defaults() {
+    // Fields without a default are passed to new(), in order
+    let mut list = lists::List::<u32>::new("Numbers".to_string(), 3);
+    assert_eq!(list.get_name(), "Numbers");
+    assert_eq!(*list.get_limit(), 3);
+    assert!(!list.is_hidden());
+
+    list.get_items_mut().push(1);
+    list.set_limit(4);
+    list.hide();
+    assert_eq!(*list.get_items(), vec![1]);
+    assert_eq!(*list.get_limit(), 4);
+    assert!(list.is_hidden());
+
+    let mut changed = list.changes().changed_fields();
+    changed.sort();
+    assert_eq!(changed, vec!["hidden", "items", "limit"]);
+}
+
+#[test]
+fn derive_on_a_plain_struct() {
+    let mut counter = Counter { count: 0, changes: Changes::new() };
+    counter.changes_mut().finish_frame();
+    counter.set_count(1);
+    *counter.get_count_mut() += 1;
+    assert_eq!(*counter.get_count(), 2);
+    assert_eq!(counter.changes().revision("count"), 3);
+}
+
+#[test]
+fn computed_fields_are_kept_until_a_dependency_changes() {
+    let mut todo = Todo::new();
+    let computed = || HEADINGS_COMPUTED.with(|computed| computed.get());
+
+    assert_eq!(*todo.heading(), "Todo (0)");
+    assert_eq!(*todo.heading(), "Todo (0)");
+    assert_eq!(computed(), 1);
+
+    todo.get_items_mut().push("Milk".to_string());
+    assert_eq!(*todo.heading(), "Todo (1)");
+    assert_eq!(*todo.count(), 1);
+    assert_eq!(computed(), 2);
+
+    todo.set_title("Groceries".to_string());
+    assert_eq!(*todo.heading(), "Groceries (1)");
+    assert_eq!(computed(), 3);
+
+    // Only the fields it is computed from count
+    let count = todo.count();
+    todo.set_title("Shopping".to_string());
+    assert!(Rc::ptr_eq(&count, &todo.count()));
+
+    // Clones compute their values again
+    let clone = todo.clone();
+    assert_eq!(*clone.heading(), "Shopping (1)");
+    assert_eq!(computed(), 4);
+}
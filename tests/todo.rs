@@ -0,0 +1,134 @@
+//! Drives the todo app from `src/app.rs` through the headless backend, the way a user would
+
+#[macro_use]
+extern crate ion;
+#[macro_use]
+extern crate ion_derive;
+#[macro_use]
+extern crate serde_derive;
+
+#[path = "../ion_servo/src/app.rs"]
+mod app;
+
+use ion::backend::headless::Harness;
+
+fn click(harness: &mut Harness, text: &str) {
+    let button = harness.find_by_text(text).unwrap_or_else(|| panic!("There is no {:?} button", text));
+    harness.fire(&button, "click");
+    harness.frame();
+}
+
+fn type_into(harness: &mut Harness, key: &str, value: &str) {
+    let input = harness.find_by_key(key).unwrap_or_else(|| panic!("There is no input with the key {:?}", key));
+    input.set_value(value);
+    harness.fire(&input, "input");
+    harness.fire(&input, "keyup");
+    harness.frame();
+}
+
+fn item_names(harness: &Harness) -> Vec<String> {
+    harness.find_by_tag("p").iter().map(|p| p.text()).collect()
+}
+
+#[test]
+fn add_item() {
+    let mut harness = Harness::new(app::app_setup);
+    assert_eq!(item_names(&harness), vec!["Testing!"]);
+    assert_eq!(harness.find_by_key("add_input").unwrap().value(), "Item Name");
+
+    type_into(&mut harness, "add_input", "Milk");
+    click(&mut harness, "+");
+    assert_eq!(item_names(&harness), vec!["Testing!", "Milk"]);
+    assert_eq!(harness.find_by_key("add_input").unwrap().value(), "Item Name");
+
+    type_into(&mut harness, "add_input", "Eggs");
+    click(&mut harness, "+");
+    assert_eq!(item_names(&harness), vec!["Testing!", "Milk", "Eggs"]);
+}
+
+#[test]
+fn edit_and_save() {
+    let mut harness = Harness::new(app::app_setup);
+    assert!(harness.find_by_key("item_1").is_none());
+
+    click(&mut harness, "Edit");
+    assert_eq!(harness.find_by_key("item_1").unwrap().value(), "Testing!");
+    assert!(harness.find_by_text("Save").is_some());
+    assert!(harness.find_by_text("Edit").is_none());
+
+    type_into(&mut harness, "item_1", "Tested");
+    click(&mut harness, "Save");
+    assert!(harness.find_by_key("item_1").is_none());
+    assert!(harness.find_by_text("Edit").is_some());
+    assert_eq!(item_names(&harness), vec!["Tested"]);
+}
+
+#[test]
+fn editing_one_item_leaves_the_others_alone() {
+    let mut harness = Harness::new(app::app_setup);
+    type_into(&mut harness, "add_input", "Milk");
+    click(&mut harness, "+");
+
+    click(&mut harness, "Edit");
+    assert!(harness.find_by_key("item_1").is_some());
+    assert!(harness.find_by_key("item_2").is_none());
+    assert_eq!(item_names(&harness), vec!["Milk"]);
+
+    // The new item keeps its own Edit button while the first one is being edited
+    click(&mut harness, "Edit");
+    assert!(harness.find_by_key("item_2").is_some());
+    assert!(item_names(&harness).is_empty());
+}
+
+#[test]
+fn undo_and_redo() {
+    let mut harness = Harness::new(app::app_setup);
+    type_into(&mut harness, "add_input", "M");
+    type_into(&mut harness, "add_input", "Mi");
+    type_into(&mut harness, "add_input", "Milk");
+    click(&mut harness, "+");
+    assert_eq!(item_names(&harness), vec!["Testing!", "Milk"]);
+
+    click(&mut harness, "Undo");
+    assert_eq!(item_names(&harness), vec!["Testing!"]);
+    assert_eq!(harness.find_by_key("add_input").unwrap().value(), "Milk");
+
+    // Typing is undone as a whole
+    click(&mut harness, "Undo");
+    assert_eq!(harness.find_by_key("add_input").unwrap().value(), "Item Name");
+
+    click(&mut harness, "Redo");
+    click(&mut harness, "Redo");
+    assert_eq!(item_names(&harness), vec!["Testing!", "Milk"]);
+    assert_eq!(harness.find_by_key("add_input").unwrap().value(), "Item Name");
+}
+
+#[test]
+fn undo_a_rename() {
+    let mut harness = Harness::new(app::app_setup);
+    click(&mut harness, "Edit");
+    type_into(&mut harness, "item_1", "Test");
+    type_into(&mut harness, "item_1", "Tested");
+    click(&mut harness, "Save");
+    assert_eq!(item_names(&harness), vec!["Tested"]);
+
+    click(&mut harness, "Undo");
+    assert_eq!(item_names(&harness), vec!["Testing!"]);
+    click(&mut harness, "Redo");
+    assert_eq!(item_names(&harness), vec!["Tested"]);
+}
+
+#[test]
+fn keys_that_change_nothing_are_not_undone() {
+    let mut harness = Harness::new(app::app_setup);
+    type_into(&mut harness, "add_input", "Milk");
+    click(&mut harness, "Edit");
+
+    // Like pressing an arrow key, which only sends keyup
+    let input = harness.find_by_key("item_1").unwrap();
+    harness.fire(&input, "keyup");
+    harness.frame();
+
+    click(&mut harness, "Undo");
+    assert_eq!(harness.find_by_key("add_input").unwrap().value(), "Item Name");
+}